@@ -2,7 +2,12 @@
 #![allow(clippy::toplevel_ref_arg)]
 use crate::Error;
 use casbin::{error::AdapterError, Error as CasbinError, Filter, Result};
-use sqlx::error::Error as SqlxError;
+use futures::{stream, TryStreamExt};
+use sqlx::{error::Error as SqlxError, QueryBuilder};
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::models::{CasbinRule, NewCasbinRule};
 
@@ -24,6 +29,33 @@ pub type ConnectionPool = sqlx::MySqlPool;
 #[cfg(feature = "sqlite")]
 pub type ConnectionPool = sqlx::SqlitePool;
 
+#[cfg(feature = "postgres")]
+pub type Connection = sqlx::PgConnection;
+
+#[cfg(feature = "mysql")]
+pub type Connection = sqlx::MySqlConnection;
+
+#[cfg(feature = "sqlite")]
+pub type Connection = sqlx::SqliteConnection;
+
+#[cfg(feature = "postgres")]
+pub type Database = sqlx::Postgres;
+
+#[cfg(feature = "mysql")]
+pub type Database = sqlx::MySql;
+
+#[cfg(feature = "sqlite")]
+pub type Database = sqlx::Sqlite;
+
+pub type Transaction = sqlx::Transaction<'static, Database>;
+
+/// The column type of optional text columns.
+#[cfg(feature = "postgres")]
+pub(crate) const TEXT: &str = "VARCHAR";
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) const TEXT: &str = "VARCHAR(255)";
+
 #[cfg(feature = "postgres")]
 pub async fn new(conn: &ConnectionPool) -> Result<PgQueryResult> {
     sqlx::query!(
@@ -45,11 +77,16 @@ pub async fn new(conn: &ConnectionPool) -> Result<PgQueryResult> {
     .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
 }
 
+/// The column definition earlier versions created the sqlite table with,
+/// which does not make `id` an alias of the rowid, so every id was NULL.
+#[cfg(feature = "sqlite")]
+const SERIAL_ID: &str = "id SERIAL PRIMARY KEY";
+
 #[cfg(feature = "sqlite")]
 pub async fn new(conn: &ConnectionPool) -> Result<SqliteQueryResult> {
     sqlx::query!(
         "CREATE TABLE IF NOT EXISTS casbin_rule (
-                    id SERIAL PRIMARY KEY,
+                    id INTEGER PRIMARY KEY,
                     ptype VARCHAR NOT NULL,
                     v0 VARCHAR NOT NULL,
                     v1 VARCHAR NOT NULL,
@@ -66,6 +103,66 @@ pub async fn new(conn: &ConnectionPool) -> Result<SqliteQueryResult> {
     .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
 }
 
+#[cfg(feature = "sqlite")]
+async fn table_sql<'e, E>(conn: E) -> Result<String>
+where
+    E: sqlx::Executor<'e, Database = Database>,
+{
+    let (sql,): (String,) = sqlx::query_as(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'casbin_rule'",
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(sql)
+}
+
+/// Rebuilds a table created with [`SERIAL_ID`] with `id INTEGER PRIMARY KEY`
+/// instead, which numbers the existing rows, returning whether it had to.
+/// Columns added since and indexes are carried over, and either all of it is
+/// done or none of it.
+#[cfg(feature = "sqlite")]
+pub async fn rebuild_serial_id(conn: &ConnectionPool) -> Result<bool> {
+    let mut transaction = conn
+        .begin_with("BEGIN IMMEDIATE")
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    let sql = table_sql(&mut *transaction).await?;
+    if !sql.contains(SERIAL_ID) {
+        return Ok(false);
+    }
+    let indexes: Vec<(String,)> = sqlx::query_as(
+        "SELECT sql FROM sqlite_master
+            WHERE type = 'index' AND tbl_name = 'casbin_rule' AND sql IS NOT NULL",
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    let statements = vec![
+        "ALTER TABLE casbin_rule RENAME TO casbin_rule_serial".to_owned(),
+        sql.replacen(SERIAL_ID, "id INTEGER PRIMARY KEY", 1),
+        "INSERT INTO casbin_rule SELECT * FROM casbin_rule_serial".to_owned(),
+        "DROP TABLE casbin_rule_serial".to_owned(),
+    ];
+    for statement in statements
+        .into_iter()
+        .chain(indexes.into_iter().map(|(sql,)| sql))
+    {
+        sqlx::query(&statement)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(true)
+}
+
 #[cfg(feature = "mysql")]
 pub async fn new(conn: &ConnectionPool) -> Result<MySqlQueryResult> {
     sqlx::query!(
@@ -88,7 +185,7 @@ pub async fn new(conn: &ConnectionPool) -> Result<MySqlQueryResult> {
 }
 
 #[cfg(feature = "postgres")]
-pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -> Result<bool> {
+pub async fn remove_policy(conn: &mut Connection, pt: &str, rule: Vec<String>) -> Result<bool> {
     let rule = normalize_casbin_rule(rule);
     sqlx::query!(
         "DELETE FROM casbin_rule WHERE
@@ -114,7 +211,7 @@ pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -
 }
 
 #[cfg(feature = "sqlite")]
-pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -> Result<bool> {
+pub async fn remove_policy(conn: &mut Connection, pt: &str, rule: Vec<String>) -> Result<bool> {
     let rule = normalize_casbin_rule(rule);
     sqlx::query!(
         "DELETE FROM casbin_rule WHERE
@@ -140,7 +237,7 @@ pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -
 }
 
 #[cfg(feature = "mysql")]
-pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -> Result<bool> {
+pub async fn remove_policy(conn: &mut Connection, pt: &str, rule: Vec<String>) -> Result<bool> {
     let rule = normalize_casbin_rule(rule);
     sqlx::query!(
         "DELETE FROM casbin_rule WHERE
@@ -167,14 +264,10 @@ pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -
 
 #[cfg(feature = "postgres")]
 pub async fn remove_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     rules: Vec<Vec<String>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         let rule = normalize_casbin_rule(rule);
         sqlx::query!(
@@ -194,7 +287,7 @@ pub async fn remove_policies(
             rule[4],
             rule[5]
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if PgQueryResult::rows_affected(&n) == 1 {
@@ -205,23 +298,15 @@ pub async fn remove_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "sqlite")]
 pub async fn remove_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     rules: Vec<Vec<String>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         let rule = normalize_casbin_rule(rule);
         sqlx::query!(
@@ -241,7 +326,7 @@ pub async fn remove_policies(
             rule[4],
             rule[5]
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if SqliteQueryResult::rows_affected(&n) == 1 {
@@ -252,23 +337,15 @@ pub async fn remove_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "mysql")]
 pub async fn remove_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     rules: Vec<Vec<String>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         let rule = normalize_casbin_rule(rule);
         sqlx::query!(
@@ -288,7 +365,7 @@ pub async fn remove_policies(
             rule[4],
             rule[5]
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if MySqlQueryResult::rows_affected(&n) == 1 {
@@ -299,16 +376,12 @@ pub async fn remove_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "postgres")]
 pub async fn remove_filtered_policy(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     field_index: usize,
     field_values: Vec<String>,
@@ -403,7 +476,7 @@ pub async fn remove_filtered_policy(
 
 #[cfg(feature = "sqlite")]
 pub async fn remove_filtered_policy(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     field_index: usize,
     field_values: Vec<String>,
@@ -498,7 +571,7 @@ pub async fn remove_filtered_policy(
 
 #[cfg(feature = "mysql")]
 pub async fn remove_filtered_policy(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     pt: &str,
     field_index: usize,
     field_values: Vec<String>,
@@ -591,43 +664,384 @@ pub async fn remove_filtered_policy(
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
 }
 
+/// The conditions, beyond those of the query at hand, a stored rule has to
+/// meet to be part of the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Visibility {
+    /// Rules with a `deleted_at` are soft deleted.
+    pub soft_delete: bool,
+    /// Rules only apply between `valid_from` and `valid_until`, if set.
+    pub validity: bool,
+}
+
+impl Visibility {
+    pub(crate) fn push_conditions(&self, query: &mut QueryBuilder<'_, Database>) {
+        if self.soft_delete {
+            query.push(" AND deleted_at IS NULL");
+        }
+
+        if self.validity {
+            let now = timestamp(SystemTime::now());
+            query
+                .push(" AND (valid_from IS NULL OR valid_from <= ")
+                .push_bind(now)
+                .push(") AND (valid_until IS NULL OR valid_until > ")
+                .push_bind(now)
+                .push(")");
+        }
+    }
+}
+
+/// Whether the `casbin_rule` table has `column`.
+pub(crate) async fn column_exists(conn: &ConnectionPool, column: &str) -> bool {
+    let select = format!("SELECT {} FROM casbin_rule WHERE 1 = 0", column);
+    sqlx::query(&select).execute(conn).await.is_ok()
+}
+
+/// Adds `column` to the `casbin_rule` table unless it is already there.
+pub(crate) async fn add_column(
+    conn: &ConnectionPool,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    if column_exists(conn, column).await {
+        return Ok(());
+    }
+
+    let alter = format!(
+        "ALTER TABLE casbin_rule ADD COLUMN {} {}",
+        column, definition
+    );
+    if let Err(err) = sqlx::query(&alter).execute(conn).await {
+        // another adapter may have added it in the meantime
+        if !column_exists(conn, column).await {
+            return Err(CasbinError::from(AdapterError(Box::new(Error::SqlxError(
+                err,
+            )))));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads all rules that are part of the policy.
+pub(crate) async fn select_policy<'e, E>(conn: E, visibility: Visibility) -> Result<Vec<CasbinRule>>
+where
+    E: sqlx::Executor<'e, Database = Database>,
+{
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE 1 = 1",
+    );
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Hands each rule that is part of the policy to `f` as it is read, without
+/// holding them all in memory.
+pub(crate) async fn for_each_policy<F>(
+    conn: &ConnectionPool,
+    visibility: Visibility,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(CasbinRule),
+{
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE 1 = 1",
+    );
+    visibility.push_conditions(&mut query);
+
+    let mut rules = query.build_query_as::<CasbinRule>().fetch(conn);
+    while let Some(rule) = rules
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?
+    {
+        f(rule);
+    }
+
+    Ok(())
+}
+
+/// Like [`for_each_policy`], but reads each ptype, split further into
+/// `partitions` by row id, over a connection of its own, all at once.
+pub(crate) async fn for_each_policy_parallel<F>(
+    conn: &ConnectionPool,
+    partitions: u32,
+    visibility: Visibility,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(CasbinRule),
+{
+    let mut query =
+        QueryBuilder::<Database>::new("SELECT DISTINCT ptype FROM casbin_rule WHERE 1 = 1");
+    visibility.push_conditions(&mut query);
+    let ptypes = query
+        .build_query_as::<(String,)>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    let partitions = partitions.max(1);
+    let mut queries = vec![];
+    for (ptype,) in ptypes {
+        for partition in 0..partitions {
+            let mut query = QueryBuilder::<Database>::new(
+                "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE ptype = ",
+            );
+            query.push_bind(ptype.clone());
+            if partitions > 1 {
+                query.push(format!(" AND id % {} = {}", partitions, partition));
+            }
+            visibility.push_conditions(&mut query);
+            queries.push(query);
+        }
+    }
+
+    let mut rules = stream::select_all(
+        queries
+            .iter_mut()
+            .map(|query| query.build_query_as::<CasbinRule>().fetch(conn)),
+    );
+    while let Some(rule) = rules
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?
+    {
+        f(rule);
+    }
+
+    Ok(())
+}
+
+/// The runtime checked counterpart of [`load_filtered_policy`], for when
+/// `visibility` adds conditions or only rules tagged `tag` are wanted.
+pub(crate) async fn select_policy_like(
+    conn: &ConnectionPool,
+    filter: &Filter<'_>,
+    tag: Option<&str>,
+    visibility: Visibility,
+) -> Result<Vec<CasbinRule>> {
+    let (g_filter, p_filter) = filtered_where_values(filter);
+
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE (",
+    );
+    for (idx, (prefix, values)) in [("g%", g_filter), ("p%", p_filter)].iter().enumerate() {
+        if idx > 0 {
+            query.push(" OR ");
+        }
+        query.push("(ptype LIKE ").push_bind(*prefix);
+        for (field, value) in values.iter().enumerate() {
+            query
+                .push(format!(" AND v{} LIKE ", field))
+                .push_bind(*value);
+        }
+        query.push(")");
+    }
+    query.push(")");
+    if let Some(tag) = tag {
+        query.push(" AND tag = ").push_bind(tag.to_owned());
+    }
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Reads the rules a `remove_filtered_policy` with the same arguments removes.
+pub(crate) async fn select_filtered_policy<'e, E>(
+    conn: E,
+    pt: &str,
+    field_index: usize,
+    field_values: &[String],
+    visibility: Visibility,
+) -> Result<Vec<CasbinRule>>
+where
+    E: sqlx::Executor<'e, Database = Database>,
+{
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE ptype = ",
+    );
+    query.push_bind(pt);
+    push_field_conditions(&mut query, field_index, field_values);
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Deletes the rows of `rules`, returning how many were deleted.
+pub(crate) async fn delete_rules(conn: &mut Connection, rules: &[CasbinRule]) -> Result<u64> {
+    let mut deleted = 0;
+
+    // stay well below the bind parameter limits of all databases
+    for chunk in rules.chunks(1000) {
+        let mut query = QueryBuilder::<Database>::new("DELETE FROM casbin_rule WHERE id IN (");
+        let mut ids = query.separated(", ");
+        for rule in chunk {
+            ids.push_bind(rule.id);
+        }
+        ids.push_unseparated(")");
+        deleted += query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map(|n| n.rows_affected())
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(deleted)
+}
+
+/// Makes the stored rules equal to `rules` by deleting the rows no longer
+/// wanted and inserting the missing ones, so the rows that stay keep their
+/// other columns. Returns the inserted rules.
+///
+/// Soft deleted copies of the missing rules are purged first, as they would
+/// keep them from being inserted.
+pub(crate) async fn sync_policy<'a>(
+    conn: &mut Connection,
+    rules: Vec<NewCasbinRule<'a>>,
+    visibility: Visibility,
+) -> Result<Vec<NewCasbinRule<'a>>> {
+    fn key(ptype: &str, values: [&str; 6]) -> (String, [String; 6]) {
+        (ptype.to_owned(), values.map(|x| x.to_owned()))
+    }
+
+    let wanted = rules
+        .iter()
+        .map(|x| key(x.ptype, [x.v0, x.v1, x.v2, x.v3, x.v4, x.v5]))
+        .collect::<HashSet<_>>();
+
+    let mut existing = HashSet::new();
+    let mut stale = vec![];
+    for rule in select_policy(&mut *conn, visibility).await? {
+        let values = [&rule.v0, &rule.v1, &rule.v2, &rule.v3, &rule.v4, &rule.v5];
+        let key = key(&rule.ptype, values.map(|x| x.as_str()));
+        if !wanted.contains(&key) {
+            stale.push(rule);
+        }
+        existing.insert(key);
+    }
+
+    let missing = rules
+        .into_iter()
+        .filter(|x| !existing.contains(&key(x.ptype, [x.v0, x.v1, x.v2, x.v3, x.v4, x.v5])))
+        .collect::<Vec<_>>();
+
+    delete_rules(conn, &stale).await?;
+    if visibility.soft_delete {
+        crate::soft_delete::purge_rules(conn, &missing).await?;
+    }
+    add_policies(conn, missing.clone()).await?;
+    Ok(missing)
+}
+
+/// Matches exactly `rule` of type `pt`.
+pub(crate) fn push_rule_conditions(
+    query: &mut QueryBuilder<'_, Database>,
+    pt: &str,
+    rule: Vec<String>,
+) {
+    query.push(" WHERE ptype = ").push_bind(pt.to_owned());
+    for (idx, value) in normalize_casbin_rule(rule).into_iter().enumerate() {
+        query.push(format!(" AND v{} = ", idx)).push_bind(value);
+    }
+}
+
+/// Matches `field_values` from field `field_index` on, with empty values
+/// matching anything.
+pub(crate) fn push_field_conditions(
+    query: &mut QueryBuilder<'_, Database>,
+    field_index: usize,
+    field_values: &[String],
+) {
+    for (idx, value) in field_values.iter().enumerate() {
+        if !value.is_empty() {
+            query
+                .push(format!(" AND v{} = ", field_index + idx))
+                .push_bind(value.clone());
+        }
+    }
+}
+
 #[cfg(feature = "postgres")]
-pub(crate) async fn load_policy(conn: &ConnectionPool) -> Result<Vec<CasbinRule>> {
-    let casbin_rule: Vec<CasbinRule> = sqlx::query_as!(
+pub(crate) async fn load_policy<F>(conn: &ConnectionPool, mut f: F) -> Result<()>
+where
+    F: FnMut(CasbinRule),
+{
+    let mut rules = sqlx::query_as!(
         CasbinRule,
         "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule"
     )
-    .fetch_all(conn)
-    .await
-    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    .fetch(conn);
 
-    Ok(casbin_rule)
+    while let Some(rule) = rules
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?
+    {
+        f(rule);
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "sqlite")]
-pub(crate) async fn load_policy(conn: &ConnectionPool) -> Result<Vec<CasbinRule>> {
-    let casbin_rule: Vec<CasbinRule> = sqlx::query_as!(
+pub(crate) async fn load_policy<F>(conn: &ConnectionPool, mut f: F) -> Result<()>
+where
+    F: FnMut(CasbinRule),
+{
+    let mut rules = sqlx::query_as!(
         CasbinRule,
         "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule"
     )
-    .fetch_all(conn)
-    .await
-    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    .fetch(conn);
 
-    Ok(casbin_rule)
+    while let Some(rule) = rules
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?
+    {
+        f(rule);
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "mysql")]
-pub(crate) async fn load_policy(conn: &ConnectionPool) -> Result<Vec<CasbinRule>> {
-    let casbin_rule: Vec<CasbinRule> = sqlx::query_as!(
+pub(crate) async fn load_policy<F>(conn: &ConnectionPool, mut f: F) -> Result<()>
+where
+    F: FnMut(CasbinRule),
+{
+    let mut rules = sqlx::query_as!(
         CasbinRule,
         "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule"
     )
-    .fetch_all(conn)
-    .await
-    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    .fetch(conn);
 
-    Ok(casbin_rule)
+    while let Some(rule) = rules
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?
+    {
+        f(rule);
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "postgres")]
@@ -718,15 +1132,11 @@ fn filtered_where_values<'a>(filter: &Filter<'a>) -> ([&'a str; 6], [&'a str; 6]
 
 #[cfg(feature = "postgres")]
 pub(crate) async fn save_policy(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'_>>,
 ) -> Result<()> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
@@ -741,7 +1151,7 @@ pub(crate) async fn save_policy(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if PgQueryResult::rows_affected(&n) == 1 {
@@ -752,24 +1162,16 @@ pub(crate) async fn save_policy(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
 #[cfg(feature = "sqlite")]
 pub(crate) async fn save_policy(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'_>>,
 ) -> Result<()> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
@@ -784,7 +1186,7 @@ pub(crate) async fn save_policy(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if SqliteQueryResult::rows_affected(&n) == 1 {
@@ -795,24 +1197,16 @@ pub(crate) async fn save_policy(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
 #[cfg(feature = "mysql")]
 pub(crate) async fn save_policy<'a>(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'a>>,
 ) -> Result<()> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
@@ -827,7 +1221,7 @@ pub(crate) async fn save_policy<'a>(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if MySqlQueryResult::rows_affected(&n) == 1 {
@@ -838,15 +1232,11 @@ pub(crate) async fn save_policy<'a>(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
 #[cfg(feature = "postgres")]
-pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -> Result<bool> {
+pub(crate) async fn add_policy(conn: &mut Connection, rule: NewCasbinRule<'_>) -> Result<bool> {
     sqlx::query!(
         "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
                  VALUES ( $1, $2, $3, $4, $5, $6, $7 )",
@@ -867,7 +1257,7 @@ pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -
 }
 
 #[cfg(feature = "sqlite")]
-pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -> Result<bool> {
+pub(crate) async fn add_policy(conn: &mut Connection, rule: NewCasbinRule<'_>) -> Result<bool> {
     sqlx::query!(
         "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
                  VALUES ( $1, $2, $3, $4, $5, $6, $7 )",
@@ -888,7 +1278,7 @@ pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -
 }
 
 #[cfg(feature = "mysql")]
-pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -> Result<bool> {
+pub(crate) async fn add_policy(conn: &mut Connection, rule: NewCasbinRule<'_>) -> Result<bool> {
     sqlx::query!(
         "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
                  VALUES ( ?, ?, ?, ?, ?, ?, ? )",
@@ -910,13 +1300,9 @@ pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -
 
 #[cfg(feature = "postgres")]
 pub(crate) async fn add_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'_>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         sqlx::query!(
             "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
@@ -929,7 +1315,7 @@ pub(crate) async fn add_policies(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if PgQueryResult::rows_affected(&n) == 1 {
@@ -940,22 +1326,14 @@ pub(crate) async fn add_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "sqlite")]
 pub(crate) async fn add_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'_>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         sqlx::query!(
             "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
@@ -968,7 +1346,7 @@ pub(crate) async fn add_policies(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if SqliteQueryResult::rows_affected(&n) == 1 {
@@ -979,22 +1357,14 @@ pub(crate) async fn add_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "mysql")]
 pub(crate) async fn add_policies(
-    conn: &ConnectionPool,
+    conn: &mut Connection,
     rules: Vec<NewCasbinRule<'_>>,
 ) -> Result<bool> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     for rule in rules {
         sqlx::query!(
             "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
@@ -1007,7 +1377,7 @@ pub(crate) async fn add_policies(
             rule.v4,
             rule.v5
         )
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .and_then(|n| {
             if MySqlQueryResult::rows_affected(&n) == 1 {
@@ -1018,65 +1388,94 @@ pub(crate) async fn add_policies(
         })
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     }
-    transaction
-        .commit()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(true)
 }
 
 #[cfg(feature = "postgres")]
-pub(crate) async fn clear_policy(conn: &ConnectionPool) -> Result<()> {
-    let mut transaction = conn
-        .begin()
+const INSERT_MISSING: &str = "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
+                 VALUES ( $1, $2, $3, $4, $5, $6, $7 ) ON CONFLICT DO NOTHING";
+
+#[cfg(feature = "sqlite")]
+const INSERT_MISSING: &str = "INSERT OR IGNORE INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
+                 VALUES ( $1, $2, $3, $4, $5, $6, $7 )";
+
+#[cfg(feature = "mysql")]
+const INSERT_MISSING: &str = "INSERT IGNORE INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
+                 VALUES ( ?, ?, ?, ?, ?, ?, ? )";
+
+/// Inserts `rule` unless it is already stored, returning whether it was.
+pub(crate) async fn add_missing(conn: &mut Connection, rule: NewCasbinRule<'_>) -> Result<bool> {
+    sqlx::query(INSERT_MISSING)
+        .bind(rule.ptype)
+        .bind(rule.v0)
+        .bind(rule.v1)
+        .bind(rule.v2)
+        .bind(rule.v3)
+        .bind(rule.v4)
+        .bind(rule.v5)
+        .execute(conn)
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(feature = "postgres")]
+pub(crate) async fn clear_policy(conn: &mut Connection) -> Result<()> {
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
-    transaction
-        .commit()
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
 #[cfg(feature = "sqlite")]
-pub(crate) async fn clear_policy(conn: &ConnectionPool) -> Result<()> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+pub(crate) async fn clear_policy(conn: &mut Connection) -> Result<()> {
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
-    transaction
-        .commit()
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
 #[cfg(feature = "mysql")]
-pub(crate) async fn clear_policy(conn: &ConnectionPool) -> Result<()> {
-    let mut transaction = conn
-        .begin()
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+pub(crate) async fn clear_policy(conn: &mut Connection) -> Result<()> {
     sqlx::query!("DELETE FROM casbin_rule")
-        .execute(&mut *transaction)
+        .execute(&mut *conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
-    transaction
-        .commit()
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+pub(crate) async fn notify(conn: &mut Connection, channel: &str, payload: &str) -> Result<()> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(payload)
+        .execute(conn)
         .await
         .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
     Ok(())
 }
 
-fn normalize_casbin_rule(mut rule: Vec<String>) -> Vec<String> {
+/// Milliseconds since the Unix epoch, the portable timestamp format of the
+/// adapter's bookkeeping tables.
+pub(crate) fn timestamp(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    }
+}
+
+/// The inverse of [`timestamp`].
+pub(crate) fn from_timestamp(millis: i64) -> SystemTime {
+    if millis >= 0 {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(millis.unsigned_abs())
+    }
+}
+
+pub(crate) fn normalize_casbin_rule(mut rule: Vec<String>) -> Vec<String> {
     rule.resize(6, String::new());
     rule
 }
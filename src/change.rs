@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+/// A committed write to the `casbin_rule` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PolicyChange {
+    Added {
+        ptype: String,
+        rules: Vec<Vec<String>>,
+    },
+    Removed {
+        ptype: String,
+        rules: Vec<Vec<String>>,
+    },
+    RemovedFiltered {
+        ptype: String,
+        field_index: usize,
+        field_values: Vec<String>,
+    },
+    Saved,
+    Cleared,
+}
+
+impl PolicyChange {
+    /// Encodes the change as JSON, the format used in notification payloads.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Decodes a payload produced by [`PolicyChange::to_json`].
+    pub fn from_json(payload: &str) -> Option<Self> {
+        serde_json::from_str(payload).ok()
+    }
+}
@@ -1,9 +1,41 @@
 use sqlx::error::Error as SqlxError;
-use std::{error::Error as StdError, fmt};
+use std::{error::Error as StdError, fmt, time::Duration};
+
+use crate::hook::Rejection;
 
 #[derive(Debug)]
 pub enum Error {
     SqlxError(SqlxError),
+    /// The database could not be reached, so the adapter serves the last
+    /// known good policy and rejects writes until a load succeeds again.
+    Degraded,
+    /// The policy was written by someone else since it was loaded, so saving
+    /// it would overwrite their changes. `loaded` is `None` if the adapter
+    /// never loaded the policy at a known version.
+    ConcurrentModification {
+        loaded: Option<i64>,
+        stored: i64,
+    },
+    /// The adapter was created read-only, see
+    /// [`SqlxAdapter::new_read_only`](crate::SqlxAdapter::new_read_only).
+    ReadOnly,
+    /// A [`PolicyHook`](crate::PolicyHook) refused the write.
+    Rejected(Rejection),
+    /// Another writer held the write lock for longer than the timeout.
+    LockTimeout {
+        key: i64,
+        timeout: Duration,
+    },
+    /// A rule was added with a value for an optional column the adapter does
+    /// not write, such as a comment without
+    /// [`SqlxAdapter::with_metadata`](crate::SqlxAdapter::with_metadata).
+    MissingColumn(&'static str),
+    /// An outbox event holds a payload that is not a
+    /// [`PolicyChange`](crate::PolicyChange), so it was left pending.
+    UndecodableEvent {
+        id: i64,
+        payload: String,
+    },
 }
 
 impl fmt::Display for Error {
@@ -12,6 +44,40 @@ impl fmt::Display for Error {
 
         match self {
             SqlxError(sqlx_error) => sqlx_error.fmt(f),
+            Rejected(rejection) => rejection.fmt(f),
+            ConcurrentModification {
+                loaded: Some(loaded),
+                stored,
+            } => write!(
+                f,
+                "the policy changed since it was loaded at version {}, it is at version {} now",
+                loaded, stored
+            ),
+            ConcurrentModification {
+                loaded: None,
+                stored,
+            } => write!(
+                f,
+                "the policy was not loaded at a known version, it is at version {} now",
+                stored
+            ),
+            LockTimeout { key, timeout } => write!(
+                f,
+                "the write lock {} was not acquired within {:?}",
+                key, timeout
+            ),
+            UndecodableEvent { id, .. } => {
+                write!(f, "the outbox event {} could not be decoded", id)
+            }
+            MissingColumn(column) => write!(
+                f,
+                "a {} was given, but the adapter does not write that column",
+                column
+            ),
+            ReadOnly => f.write_str("the adapter is read-only"),
+            Degraded => f.write_str(
+                "the database is unavailable, writes are rejected until the policy loads again",
+            ),
         }
     }
 }
@@ -22,6 +88,13 @@ impl StdError for Error {
 
         match self {
             SqlxError(sqlx_err) => Some(sqlx_err),
+            Rejected(rejection) => Some(rejection),
+            ReadOnly
+            | Degraded
+            | ConcurrentModification { .. }
+            | LockTimeout { .. }
+            | MissingColumn(_)
+            | UndecodableEvent { .. } => None,
         }
     }
 }
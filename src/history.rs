@@ -0,0 +1,638 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::{FromRow, QueryBuilder};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use crate::{
+    actions::{
+        self, from_timestamp, normalize_casbin_rule, timestamp, Connection, ConnectionPool,
+        Database,
+    },
+    error::*,
+};
+
+#[cfg(feature = "postgres")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_history (
+                    id BIGSERIAL PRIMARY KEY,
+                    op VARCHAR NOT NULL,
+                    ptype VARCHAR NOT NULL,
+                    v0 VARCHAR NOT NULL,
+                    v1 VARCHAR NOT NULL,
+                    v2 VARCHAR NOT NULL,
+                    v3 VARCHAR NOT NULL,
+                    v4 VARCHAR NOT NULL,
+                    v5 VARCHAR NOT NULL,
+                    version BIGINT,
+                    actor VARCHAR NOT NULL,
+                    created_at BIGINT NOT NULL
+                    );";
+
+#[cfg(feature = "mysql")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_history (
+                    id BIGINT NOT NULL AUTO_INCREMENT,
+                    op VARCHAR(16) NOT NULL,
+                    ptype VARCHAR(12) NOT NULL,
+                    v0 VARCHAR(128) NOT NULL,
+                    v1 VARCHAR(128) NOT NULL,
+                    v2 VARCHAR(128) NOT NULL,
+                    v3 VARCHAR(128) NOT NULL,
+                    v4 VARCHAR(128) NOT NULL,
+                    v5 VARCHAR(128) NOT NULL,
+                    version BIGINT,
+                    actor VARCHAR(128) NOT NULL,
+                    created_at BIGINT NOT NULL,
+                    PRIMARY KEY(id)
+                ) ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+#[cfg(feature = "sqlite")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    op VARCHAR(16) NOT NULL,
+                    ptype VARCHAR(12) NOT NULL,
+                    v0 VARCHAR(128) NOT NULL,
+                    v1 VARCHAR(128) NOT NULL,
+                    v2 VARCHAR(128) NOT NULL,
+                    v3 VARCHAR(128) NOT NULL,
+                    v4 VARCHAR(128) NOT NULL,
+                    v5 VARCHAR(128) NOT NULL,
+                    version BIGINT,
+                    actor VARCHAR(128) NOT NULL,
+                    created_at BIGINT NOT NULL
+                    );";
+
+thread_local! {
+    static ACTOR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A future that is the actor's while it is polled, which works the same on
+/// every runtime.
+struct Scoped<F> {
+    actor: Option<String>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        ACTOR.with(|current| mem::swap(&mut *current.borrow_mut(), &mut this.actor));
+        // hands the enclosing actor back, even if the future panics
+        let _restore = Restore(&mut this.actor);
+        this.future.as_mut().poll(cx)
+    }
+}
+
+struct Restore<'a>(&'a mut Option<String>);
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        ACTOR.with(|current| mem::swap(&mut *current.borrow_mut(), self.0));
+    }
+}
+
+pub(crate) async fn scope_actor<F: Future>(actor: impl Into<String>, f: F) -> F::Output {
+    Scoped {
+        actor: Some(actor.into()),
+        future: Box::pin(f),
+    }
+    .await
+}
+
+pub(crate) fn current_actor() -> Option<String> {
+    ACTOR.with(|actor| actor.borrow().clone())
+}
+
+/// The kind of write a [`HistoryEntry`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// The rule was added.
+    Add,
+    /// The rule was removed.
+    Remove,
+    /// All rules were removed.
+    Clear,
+    /// All rules were replaced by the `Add` entries that follow in the same
+    /// write.
+    Save,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Add => "add",
+            Operation::Remove => "remove",
+            Operation::Clear => "clear",
+            Operation::Save => "save",
+        }
+    }
+
+    fn parse(op: &str) -> Self {
+        match op {
+            "add" => Operation::Add,
+            "remove" => Operation::Remove,
+            "clear" => Operation::Clear,
+            _ => Operation::Save,
+        }
+    }
+}
+
+/// A point in the history of the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryPoint {
+    /// Right after the write that produced this policy version, which needs
+    /// [`SqlxAdapter::with_policy_version`](crate::SqlxAdapter::with_policy_version).
+    Version(i64),
+    /// The given moment.
+    Time(SystemTime),
+}
+
+/// The rules a rollback changed, as `(ptype, rule)` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rollback {
+    /// The rules added back.
+    pub added: Vec<(String, Vec<String>)>,
+    /// The rules removed.
+    pub removed: Vec<(String, Vec<String>)>,
+}
+
+/// One recorded change of the `casbin_rule_history` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub operation: Operation,
+    pub ptype: String,
+    pub rule: Vec<String>,
+    /// The policy version the write produced, if the adapter keeps one.
+    pub version: Option<i64>,
+    pub actor: String,
+    pub created_at: SystemTime,
+}
+
+#[derive(Debug, FromRow)]
+struct HistoryRow {
+    id: i64,
+    op: String,
+    ptype: String,
+    v0: String,
+    v1: String,
+    v2: String,
+    v3: String,
+    v4: String,
+    v5: String,
+    version: Option<i64>,
+    actor: String,
+    created_at: i64,
+}
+
+impl From<HistoryRow> for HistoryEntry {
+    fn from(row: HistoryRow) -> Self {
+        let mut rule = vec![row.v0, row.v1, row.v2, row.v3, row.v4, row.v5];
+        while rule.last().is_some_and(|x| x.is_empty()) {
+            rule.pop();
+        }
+
+        HistoryEntry {
+            id: row.id,
+            operation: Operation::parse(&row.op),
+            ptype: row.ptype,
+            rule,
+            version: row.version,
+            actor: row.actor,
+            created_at: from_timestamp(row.created_at),
+        }
+    }
+}
+
+/// A history entry about to be written.
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub operation: Operation,
+    pub ptype: String,
+    pub rule: Vec<String>,
+    /// The actor of the write, when it is not the current one.
+    pub actor: Option<String>,
+}
+
+impl Record {
+    pub fn new(operation: Operation, ptype: &str, rule: Vec<String>) -> Self {
+        Record {
+            operation,
+            ptype: ptype.to_owned(),
+            rule,
+            actor: None,
+        }
+    }
+
+    /// The record of a write by `actor`, such as one buffered earlier.
+    pub fn by(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_owned());
+        self
+    }
+
+    pub fn many(operation: Operation, ptype: &str, rules: Vec<Vec<String>>) -> Vec<Self> {
+        rules
+            .into_iter()
+            .map(|rule| Record::new(operation, ptype, rule))
+            .collect()
+    }
+}
+
+pub(crate) async fn create_table(conn: &ConnectionPool) -> Result<()> {
+    sqlx::query(CREATE_TABLE)
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(())
+}
+
+/// Records the stored rules as a save when the history is still empty, so
+/// that replaying it starts from the policy as it was when it was enabled.
+pub(crate) async fn record_baseline(conn: &ConnectionPool) -> Result<()> {
+    let existing: Option<(i64,)> = sqlx::query_as("SELECT id FROM casbin_rule_history LIMIT 1")
+        .fetch_optional(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    if existing.is_some() {
+        return Ok(());
+    }
+
+    let mut records = vec![Record::new(Operation::Save, "", vec![])];
+    actions::load_policy(conn, |rule| {
+        if rule.ptype.is_empty() {
+            return;
+        }
+        let mut values = vec![rule.v0, rule.v1, rule.v2, rule.v3, rule.v4, rule.v5];
+        while values.last().is_some_and(|x| x.is_empty()) {
+            values.pop();
+        }
+        records.push(Record::new(Operation::Add, &rule.ptype, values));
+    })
+    .await?;
+
+    let mut conn = conn
+        .acquire()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    record(&mut conn, records, None).await
+}
+
+pub(crate) async fn record(
+    conn: &mut Connection,
+    records: Vec<Record>,
+    version: Option<i64>,
+) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let actor = current_actor().unwrap_or_default();
+    let created_at = timestamp(SystemTime::now());
+
+    // stay well below the bind parameter limits of all databases
+    for chunk in records.chunks(1000) {
+        let mut query = QueryBuilder::<Database>::new(
+            "INSERT INTO casbin_rule_history ( op, ptype, v0, v1, v2, v3, v4, v5, version, actor, created_at ) ",
+        );
+        query.push_values(chunk, |mut row, record| {
+            row.push_bind(record.operation.as_str())
+                .push_bind(record.ptype.as_str());
+            for value in normalize_casbin_rule(record.rule.clone()) {
+                row.push_bind(value);
+            }
+            row.push_bind(version)
+                .push_bind(record.actor.as_deref().unwrap_or(&actor))
+                .push_bind(created_at);
+        });
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(())
+}
+
+/// Reads up to `limit` entries after `after_id`, oldest first.
+pub(crate) async fn entries(
+    conn: &ConnectionPool,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<HistoryEntry>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, op, ptype, v0, v1, v2, v3, v4, v5, version, actor, created_at
+            FROM casbin_rule_history WHERE id > ",
+    );
+    query
+        .push_bind(after_id)
+        .push(" ORDER BY id LIMIT ")
+        .push_bind(limit);
+    query
+        .build_query_as::<HistoryRow>()
+        .fetch_all(conn)
+        .await
+        .map(|rows| rows.into_iter().map(HistoryEntry::from).collect())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Rebuilds the rules as they were at `point`, as `(ptype, rule)` pairs.
+///
+/// Replays the entries recorded up to then, starting from the last save or
+/// clear among them. Validity windows are not recorded, so rules that were
+/// stored but not valid yet or anymore at `point` are included as well.
+pub(crate) async fn rules_at(
+    conn: &mut Connection,
+    point: HistoryPoint,
+) -> Result<BTreeSet<(String, Vec<String>)>> {
+    let mut query = QueryBuilder::<Database>::new("SELECT MAX(id) FROM casbin_rule_history WHERE ");
+    match point {
+        HistoryPoint::Version(version) => query.push("version <= ").push_bind(version),
+        HistoryPoint::Time(at) => query.push("created_at <= ").push_bind(timestamp(at)),
+    };
+    let (last_id,): (Option<i64>,) = query
+        .build_query_as()
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    match last_id {
+        Some(last_id) => rules_until(conn, last_id).await,
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Rebuilds the rules as they were right after the entry with id `last_id`.
+pub(crate) async fn rules_until(
+    conn: &mut Connection,
+    last_id: i64,
+) -> Result<BTreeSet<(String, Vec<String>)>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, op, ptype, v0, v1, v2, v3, v4, v5, version, actor, created_at
+            FROM casbin_rule_history
+            WHERE id >= (SELECT COALESCE(MAX(id), 0) FROM casbin_rule_history
+                WHERE op IN ('save', 'clear') AND id <= ",
+    );
+    query
+        .push_bind(last_id)
+        .push(") AND id <= ")
+        .push_bind(last_id)
+        .push(" ORDER BY id");
+    let entries = query
+        .build_query_as::<HistoryRow>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    let mut rules = BTreeSet::new();
+    for entry in entries.into_iter().map(HistoryEntry::from) {
+        match entry.operation {
+            Operation::Add => {
+                rules.insert((entry.ptype, entry.rule));
+            }
+            Operation::Remove => {
+                rules.remove(&(entry.ptype, entry.rule));
+            }
+            Operation::Clear | Operation::Save => rules.clear(),
+        }
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    use crate::{Operation, SqlxAdapter};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_history() {
+        use casbin::Adapter;
+
+        let mut adapter = test_util::adapter().await.with_history().await.unwrap();
+
+        let after_id = adapter
+            .history(0, i64::MAX)
+            .await
+            .unwrap()
+            .last()
+            .map_or(0, |x| x.id);
+
+        let rule = to_owned(vec!["history_alice", "history_data", "read"]);
+        SqlxAdapter::scope_actor("history_admin", async {
+            assert_eq!(
+                Some("history_admin".to_owned()),
+                SqlxAdapter::current_actor()
+            );
+            assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+            assert!(adapter
+                .remove_filtered_policy("p", "p", 0, to_owned(vec!["history_alice"]))
+                .await
+                .unwrap());
+        })
+        .await;
+        assert_eq!(None, SqlxAdapter::current_actor());
+
+        let entries = adapter
+            .history(after_id, i64::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|x| x.rule == rule)
+            .collect::<Vec<_>>();
+        assert_eq!(2, entries.len());
+        assert_eq!(Operation::Add, entries[0].operation);
+        assert_eq!(Operation::Remove, entries[1].operation);
+        assert!(entries.iter().all(|x| x.actor == "history_admin"));
+        assert!(entries.iter().all(|x| x.ptype == "p"));
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_load_policy_at() {
+        use casbin::{Adapter, DefaultModel, Model};
+        use std::time::{Duration, SystemTime};
+
+        let mut adapter = test_util::adapter().await.with_history().await.unwrap();
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+
+        let old_rule = to_owned(vec!["at_alice", "at_data1", "read"]);
+        let new_rule = to_owned(vec!["at_alice", "at_data2", "read"]);
+
+        assert!(adapter
+            .add_policy("p", "p", old_rule.clone())
+            .await
+            .unwrap());
+        crate::runtime::sleep(Duration::from_millis(10)).await;
+        let at = SystemTime::now();
+        crate::runtime::sleep(Duration::from_millis(10)).await;
+        assert!(adapter
+            .remove_policy("p", "p", old_rule.clone())
+            .await
+            .unwrap());
+        assert!(adapter
+            .add_policy("p", "p", new_rule.clone())
+            .await
+            .unwrap());
+
+        adapter.load_policy_at(&mut m, at).await.unwrap();
+        assert!(m.has_policy("p", "p", old_rule.clone()));
+        assert!(!m.has_policy("p", "p", new_rule.clone()));
+
+        adapter
+            .load_policy_at(&mut m, SystemTime::now())
+            .await
+            .unwrap();
+        assert!(!m.has_policy("p", "p", old_rule));
+        assert!(m.has_policy("p", "p", new_rule.clone()));
+
+        assert!(adapter.remove_policy("p", "p", new_rule).await.unwrap());
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_rollback() {
+        use crate::HistoryPoint;
+        use casbin::Adapter;
+
+        let mut adapter = test_util::adapter()
+            .await
+            .with_policy_version()
+            .await
+            .unwrap()
+            .with_history()
+            .await
+            .unwrap();
+
+        let kept = to_owned(vec!["rollback_alice", "rollback_data1", "read"]);
+        let reverted = to_owned(vec!["rollback_alice", "rollback_data2", "read"]);
+
+        assert!(adapter.add_policy("p", "p", kept.clone()).await.unwrap());
+        let before = adapter.current_version().await.unwrap();
+        assert!(adapter
+            .add_policy("p", "p", reverted.clone())
+            .await
+            .unwrap());
+        let after = adapter.current_version().await.unwrap();
+
+        let rollback = adapter
+            .rollback(HistoryPoint::Version(before))
+            .await
+            .unwrap();
+        assert!(rollback
+            .removed
+            .contains(&("p".to_owned(), reverted.clone())));
+        assert!(!rollback.removed.contains(&("p".to_owned(), kept.clone())));
+        assert!(!rollback.added.contains(&("p".to_owned(), kept.clone())));
+
+        let rollback = adapter
+            .rollback(HistoryPoint::Version(after))
+            .await
+            .unwrap();
+        assert!(rollback.added.contains(&("p".to_owned(), reverted.clone())));
+
+        assert!(adapter
+            .remove_policies("p", "p", vec![kept, reverted])
+            .await
+            .unwrap());
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_rollback_soft_delete() {
+        use crate::HistoryPoint;
+        use casbin::Adapter;
+        use std::time::{Duration, SystemTime};
+
+        let mut adapter = test_util::adapter()
+            .await
+            .with_policy_version()
+            .await
+            .unwrap()
+            .with_history()
+            .await
+            .unwrap()
+            .with_soft_delete()
+            .await
+            .unwrap();
+
+        let rule = to_owned(vec!["rollback_soft_alice", "rollback_soft_data", "read"]);
+        let is_deleted = |deleted: &[crate::DeletedRule]| {
+            deleted.iter().any(|x| x.ptype == "p" && x.rule == rule)
+        };
+
+        let before = adapter.current_version().await.unwrap();
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        let after = adapter.current_version().await.unwrap();
+
+        // rolling the rule back soft deletes it
+        adapter
+            .rollback(HistoryPoint::Version(before))
+            .await
+            .unwrap();
+        assert!(!adapter.rule_exists("p", rule.clone()).await.unwrap());
+        assert!(is_deleted(&adapter.list_deleted().await.unwrap()));
+
+        // and rolling forward brings it back over the soft deleted row
+        let rollback = adapter
+            .rollback(HistoryPoint::Version(after))
+            .await
+            .unwrap();
+        assert!(rollback.added.contains(&("p".to_owned(), rule.clone())));
+        assert!(adapter.rule_exists("p", rule.clone()).await.unwrap());
+        assert!(!is_deleted(&adapter.list_deleted().await.unwrap()));
+
+        assert!(adapter.remove_policy("p", "p", rule).await.unwrap());
+        adapter
+            .purge_deleted(SystemTime::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use async_trait::async_trait;
+use std::{error::Error as StdError, fmt};
+
+use crate::change::PolicyChange;
+
+/// Callbacks run around the writes of a [`SqlxAdapter`](crate::SqlxAdapter),
+/// see [`SqlxAdapter::with_hook`](crate::SqlxAdapter::with_hook).
+///
+/// Every callback does nothing by default, so a hook only implements the
+/// ones it needs. A `before_*` callback returning a [`Rejection`] stops the
+/// write before it reaches the database. The `before_*` callbacks run
+/// before the transaction of the write begins, so a slow hook holds no
+/// locks.
+///
+/// Hooks validate and veto, but do not enrich: rules reach the adapter from
+/// a model the enforcer has already updated, so a hook rewriting them would
+/// leave the two apart. Values are best settled before the rule is handed to
+/// the enforcer, and what is stored alongside a rule is set with methods
+/// such as [`SqlxAdapter::add_policy_with_metadata`] and
+/// [`SqlxAdapter::add_policy_with_tag`].
+///
+/// [`SqlxAdapter::add_policy_with_metadata`]: crate::SqlxAdapter::add_policy_with_metadata
+/// [`SqlxAdapter::add_policy_with_tag`]: crate::SqlxAdapter::add_policy_with_tag
+#[async_trait]
+pub trait PolicyHook: Send + Sync {
+    /// Called before `rules` of `ptype` are added.
+    ///
+    /// The rules cannot be changed here, since the enforcer has already put
+    /// them into its model as they are.
+    async fn before_add(&self, ptype: &str, rules: &[Vec<String>]) -> Result<(), Rejection> {
+        let _ = (ptype, rules);
+        Ok(())
+    }
+
+    /// Called before `rules` of `ptype` are removed.
+    ///
+    /// For a filtered removal these are the rules matching the filter. They
+    /// are read just before the transaction of the removal begins, and if
+    /// they changed by then the callback runs again on the new ones.
+    async fn before_remove(&self, ptype: &str, rules: &[Vec<String>]) -> Result<(), Rejection> {
+        let _ = (ptype, rules);
+        Ok(())
+    }
+
+    /// Called before the whole policy is replaced by `rules`, as
+    /// `(ptype, rule)` pairs, which are none for `clear_policy`.
+    async fn before_save(&self, rules: &[(String, Vec<String>)]) -> Result<(), Rejection> {
+        let _ = rules;
+        Ok(())
+    }
+
+    /// Called once a write has been committed, with the changes it made.
+    async fn after_commit(&self, changes: &[PolicyChange]) {
+        let _ = changes;
+    }
+}
+
+/// Why a [`PolicyHook`] refused a write, returned to the writer as
+/// [`Error::Rejected`](crate::Error::Rejected).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    reason: String,
+}
+
+impl Rejection {
+    pub fn new<S: Into<String>>(reason: S) -> Self {
+        Rejection {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the write was rejected: {}", self.reason)
+    }
+}
+
+impl StdError for Rejection {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, to_owned};
+    use crate::Error;
+    use std::sync::{Arc, Mutex};
+
+    /// Forbids granting `*` on production objects and uppercase subjects,
+    /// and keeps the protected rule and an empty policy out.
+    #[derive(Clone, Default)]
+    struct Guard {
+        committed: Arc<Mutex<Vec<PolicyChange>>>,
+    }
+
+    #[async_trait]
+    impl PolicyHook for Guard {
+        async fn before_add(&self, _ptype: &str, rules: &[Vec<String>]) -> Result<(), Rejection> {
+            for rule in rules {
+                if rule.len() > 2 && rule[1].starts_with("hook_prod") && rule[2] == "*" {
+                    return Err(Rejection::new("no wildcards on production objects"));
+                }
+                if rule[0] != rule[0].to_lowercase() {
+                    return Err(Rejection::new("subjects are lowercase"));
+                }
+            }
+            Ok(())
+        }
+
+        async fn before_remove(
+            &self,
+            _ptype: &str,
+            rules: &[Vec<String>],
+        ) -> Result<(), Rejection> {
+            if rules.iter().any(|x| x[0] == "hook_root") {
+                return Err(Rejection::new("hook_root stays"));
+            }
+            Ok(())
+        }
+
+        async fn before_save(&self, rules: &[(String, Vec<String>)]) -> Result<(), Rejection> {
+            if rules.is_empty() {
+                return Err(Rejection::new("the policy must not be emptied"));
+            }
+            Ok(())
+        }
+
+        async fn after_commit(&self, changes: &[PolicyChange]) {
+            self.committed.lock().unwrap().extend_from_slice(changes);
+        }
+    }
+
+    fn rejection(err: casbin::Error) -> Rejection {
+        match err {
+            casbin::Error::AdapterError(casbin::error::AdapterError(err)) => {
+                match err.downcast_ref::<Error>() {
+                    Some(Error::Rejected(rejection)) => rejection.clone(),
+                    _ => panic!("expected a rejection, got {}", err),
+                }
+            }
+            err => panic!("expected a rejection, got {}", err),
+        }
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_hooks() {
+        use casbin::Adapter;
+
+        let guard = Guard::default();
+        let mut adapter = test_util::adapter().await.with_hook(guard.clone());
+
+        let wildcard = to_owned(vec!["hook_alice", "hook_prod_db", "*"]);
+        assert_eq!(
+            "no wildcards on production objects",
+            rejection(
+                adapter
+                    .add_policy("p", "p", wildcard.clone())
+                    .await
+                    .unwrap_err()
+            )
+            .reason()
+        );
+        assert!(!adapter.rule_exists("p", wildcard).await.unwrap());
+
+        let upper = to_owned(vec!["HOOK_ROOT", "hook_data1", "read"]);
+        rejection(
+            adapter
+                .add_policy("p", "p", upper.clone())
+                .await
+                .unwrap_err(),
+        );
+        assert!(!adapter.rule_exists("p", upper).await.unwrap());
+
+        let root = to_owned(vec!["hook_root", "hook_data1", "read"]);
+        assert!(adapter.add_policy("p", "p", root.clone()).await.unwrap());
+
+        rejection(
+            adapter
+                .remove_policy("p", "p", root.clone())
+                .await
+                .unwrap_err(),
+        );
+        rejection(
+            adapter
+                .remove_filtered_policy("p", "p", 1, to_owned(vec!["hook_data1"]))
+                .await
+                .unwrap_err(),
+        );
+        rejection(adapter.clear_policy().await.unwrap_err());
+        assert!(adapter.rule_exists("p", root.clone()).await.unwrap());
+        assert_eq!(
+            vec![PolicyChange::Added {
+                ptype: "p".to_owned(),
+                rules: vec![root.clone()],
+            }],
+            *guard.committed.lock().unwrap()
+        );
+
+        // an adapter without the hook cleans up
+        let mut plain = test_util::adapter().await;
+        assert!(plain.remove_policy("p", "p", root).await.unwrap());
+
+        // a removal the hook lets through goes ahead
+        let other = to_owned(vec!["hook_bob", "hook_data2", "read"]);
+        assert!(adapter.add_policy("p", "p", other.clone()).await.unwrap());
+        assert!(adapter
+            .remove_filtered_policy("p", "p", 0, to_owned(vec!["hook_bob"]))
+            .await
+            .unwrap());
+        assert!(!adapter.rule_exists("p", other).await.unwrap());
+    }
+}
@@ -0,0 +1,254 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::{FromRow, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    actions::{
+        add_column, normalize_casbin_rule, push_rule_conditions, Connection, ConnectionPool,
+        Database, Visibility,
+    },
+    error::*,
+    models::{CasbinRule, NewCasbinRule},
+};
+
+#[cfg(feature = "postgres")]
+const UUID: &str = "VARCHAR";
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+const UUID: &str = "VARCHAR(36)";
+
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+const CREATE_INDEX: &str =
+    "CREATE UNIQUE INDEX IF NOT EXISTS casbin_rule_uuid ON casbin_rule ( uuid )";
+
+#[cfg(feature = "mysql")]
+const CREATE_INDEX: &str = "CREATE UNIQUE INDEX casbin_rule_uuid ON casbin_rule ( uuid )";
+
+/// A stored rule together with its stable id, see
+/// [`SqlxAdapter::with_stable_ids`](crate::SqlxAdapter::with_stable_ids).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentifiedRule {
+    pub id: Uuid,
+    pub ptype: String,
+    pub rule: Vec<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct IdentifiedRow {
+    uuid: String,
+    ptype: String,
+    v0: String,
+    v1: String,
+    v2: String,
+    v3: String,
+    v4: String,
+    v5: String,
+}
+
+impl IdentifiedRow {
+    fn into_rule(self) -> Option<IdentifiedRule> {
+        let mut rule = vec![self.v0, self.v1, self.v2, self.v3, self.v4, self.v5];
+        while rule.last().is_some_and(|x| x.is_empty()) {
+            rule.pop();
+        }
+
+        Some(IdentifiedRule {
+            id: Uuid::parse_str(&self.uuid).ok()?,
+            ptype: self.ptype,
+            rule,
+        })
+    }
+}
+
+/// Adds the `uuid` column and its index, and gives the rows without an id one.
+pub(crate) async fn add_columns(conn: &ConnectionPool) -> Result<()> {
+    add_column(conn, "uuid", UUID).await?;
+
+    #[cfg(feature = "mysql")]
+    let exists = {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM information_schema.statistics
+                WHERE table_schema = DATABASE() AND table_name = 'casbin_rule'
+                AND index_name = 'casbin_rule_uuid'",
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+        count > 0
+    };
+
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    let exists = false;
+
+    if !exists {
+        sqlx::query(CREATE_INDEX)
+            .execute(conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    let missing: Vec<CasbinRule> = sqlx::query_as(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE uuid IS NULL",
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+    for rule in missing {
+        let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET uuid = ");
+        query
+            .push_bind(Uuid::new_v4().to_string())
+            .push(" WHERE uuid IS NULL AND id = ")
+            .push_bind(rule.id);
+        query
+            .build()
+            .execute(conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(())
+}
+
+/// Gives freshly inserted `rules` an id.
+pub(crate) async fn assign(conn: &mut Connection, rules: &[NewCasbinRule<'_>]) -> Result<()> {
+    for rule in rules {
+        let values = [rule.v0, rule.v1, rule.v2, rule.v3, rule.v4, rule.v5];
+        let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET uuid = ");
+        query.push_bind(Uuid::new_v4().to_string());
+        push_rule_conditions(
+            &mut query,
+            rule.ptype,
+            values.iter().map(|x| x.to_string()).collect(),
+        );
+        query.push(" AND uuid IS NULL");
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn list(
+    conn: &ConnectionPool,
+    visibility: Visibility,
+) -> Result<Vec<IdentifiedRule>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT uuid, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE uuid IS NOT NULL",
+    );
+    visibility.push_conditions(&mut query);
+    query.push(" ORDER BY id");
+
+    query
+        .build_query_as::<IdentifiedRow>()
+        .fetch_all(conn)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .filter_map(IdentifiedRow::into_rule)
+                .collect()
+        })
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Reads the row with id `id`, if it is part of the policy.
+pub(crate) async fn select<'e, E>(
+    conn: E,
+    id: Uuid,
+    visibility: Visibility,
+) -> Result<Option<CasbinRule>>
+where
+    E: sqlx::Executor<'e, Database = Database>,
+{
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE uuid = ",
+    );
+    query.push_bind(id.to_string());
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_optional(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Replaces the values of the rule stored in `row`, keeping its id.
+pub(crate) async fn update(
+    conn: &mut Connection,
+    row: &CasbinRule,
+    rule: Vec<String>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET ");
+    let mut values = query.separated(", ");
+    for (idx, value) in normalize_casbin_rule(rule).into_iter().enumerate() {
+        values
+            .push(format!("v{} = ", idx))
+            .push_bind_unseparated(value);
+    }
+    query.push(" WHERE id = ").push_bind(row.id);
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_stable_ids() {
+        use casbin::{Adapter, DefaultModel, Model};
+
+        let mut adapter = test_util::adapter().await.with_stable_ids().await.unwrap();
+
+        let rule = to_owned(vec!["ids_alice", "ids_data1", "read"]);
+        let updated = to_owned(vec!["ids_alice", "ids_data2", "write"]);
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+
+        let id_of = |rules: &[crate::IdentifiedRule], rule: &[String]| {
+            rules.iter().find(|x| x.rule == rule).map(|x| x.id)
+        };
+        let id = id_of(&adapter.list_with_ids().await.unwrap(), &rule).unwrap();
+
+        assert!(adapter.update_by_id(id, updated.clone()).await.unwrap());
+        let found = adapter.rule_by_id(id).await.unwrap().unwrap();
+        assert_eq!("p", found.ptype);
+        assert_eq!(updated, found.rule);
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(!m.has_policy("p", "p", rule));
+        assert!(m.has_policy("p", "p", updated.clone()));
+
+        assert!(adapter.remove_by_id(id).await.unwrap());
+        assert!(!adapter.remove_by_id(id).await.unwrap());
+        assert_eq!(None, adapter.rule_by_id(id).await.unwrap());
+        assert!(!adapter.update_by_id(id, updated).await.unwrap());
+
+        // adapters created once the column is there give rules an id too
+        let detected = to_owned(vec!["ids_alice", "ids_data3", "read"]);
+        let mut plain = test_util::adapter().await;
+        assert!(plain.add_policy("p", "p", detected.clone()).await.unwrap());
+        let id = id_of(&adapter.list_with_ids().await.unwrap(), &detected).unwrap();
+        assert!(adapter.remove_by_id(id).await.unwrap());
+    }
+}
@@ -1,14 +1,55 @@
 extern crate sqlx;
 
 mod adapter;
+mod change;
+mod change_log;
 mod error;
+mod fallback;
+mod history;
+mod hook;
+mod ids;
+mod lock;
+mod metadata;
+mod outbox;
+mod query;
+mod single_flight;
+mod snapshot;
+mod soft_delete;
+mod tags;
+#[cfg(test)]
+mod test_util;
+mod validity;
+mod version;
+mod write_behind;
 
 #[macro_use]
 mod models;
 
 mod actions;
 
+mod runtime;
+#[cfg(feature = "watcher")]
+mod watcher;
+
 pub use casbin;
+pub use uuid;
 
 pub use adapter::SqlxAdapter;
+pub use change::PolicyChange;
+pub use change_log::ChangeCursor;
 pub use error::Error;
+pub use history::{HistoryEntry, HistoryPoint, Operation, Rollback};
+pub use hook::{PolicyHook, Rejection};
+pub use ids::IdentifiedRule;
+pub use metadata::RuleMetadata;
+pub use outbox::OutboxEvent;
+pub use query::{RuleFilter, StoredRule};
+pub use soft_delete::DeletedRule;
+pub use validity::Reaper;
+
+#[cfg(all(feature = "watcher", feature = "postgres"))]
+pub use watcher::PgWatcher;
+#[cfg(feature = "watcher")]
+pub use watcher::PollingWatcher;
+#[cfg(all(feature = "watcher", feature = "sqlite"))]
+pub use watcher::SqliteWatcher;
@@ -0,0 +1,186 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use std::time::Duration;
+
+use crate::{
+    actions::{ConnectionPool, Transaction},
+    error::*,
+};
+
+#[cfg(feature = "mysql")]
+use sqlx::{pool::PoolConnection, MySql};
+
+#[cfg(feature = "sqlite")]
+use crate::runtime;
+
+/// How long to wait before trying again to begin an immediate transaction.
+#[cfg(feature = "sqlite")]
+const BUSY_RETRY: Duration = Duration::from_millis(50);
+
+/// The lock serialising bulk writes, see
+/// [`SqlxAdapter::with_write_lock`](crate::SqlxAdapter::with_write_lock).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WriteLock {
+    key: i64,
+    timeout: Duration,
+}
+
+/// A write lock outliving the transaction it was taken for, until released.
+///
+/// Only mysql needs this, the other databases let go of the lock when the
+/// transaction ends.
+pub(crate) struct Held {
+    #[cfg(feature = "mysql")]
+    conn: Option<PoolConnection<MySql>>,
+    #[cfg(feature = "mysql")]
+    name: String,
+}
+
+impl WriteLock {
+    pub(crate) fn new(key: i64, timeout: Duration) -> Self {
+        WriteLock { key, timeout }
+    }
+
+    fn timed_out(&self) -> CasbinError {
+        CasbinError::from(AdapterError(Box::new(Error::LockTimeout {
+            key: self.key,
+            timeout: self.timeout,
+        })))
+    }
+
+    /// Begins a transaction holding the lock.
+    #[cfg(feature = "postgres")]
+    pub(crate) async fn begin(&self, conn: &ConnectionPool) -> Result<(Transaction, Held)> {
+        let mut transaction = conn
+            .begin()
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+        // SET takes no bind parameters
+        sqlx::query(&format!(
+            "SET LOCAL lock_timeout = '{}ms'",
+            self.timeout.as_millis().max(1)
+        ))
+        .execute(&mut *transaction)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+
+        let locked = sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(self.key)
+            .execute(&mut *transaction)
+            .await;
+        match locked {
+            Ok(_) => {}
+            // lock_not_available
+            Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some("55P03") => {
+                return Err(self.timed_out());
+            }
+            Err(err) => {
+                return Err(CasbinError::from(AdapterError(Box::new(Error::SqlxError(
+                    err,
+                )))));
+            }
+        }
+
+        sqlx::query("SET LOCAL lock_timeout TO DEFAULT")
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+        Ok((transaction, Held {}))
+    }
+
+    /// Begins a transaction holding the lock.
+    ///
+    /// `GET_LOCK` locks are held by a session rather than a transaction, so
+    /// the lock is taken on a connection of its own, which stays checked out
+    /// until the lock is released.
+    #[cfg(feature = "mysql")]
+    pub(crate) async fn begin(&self, conn: &ConnectionPool) -> Result<(Transaction, Held)> {
+        let mut held =
+            Held {
+                conn: Some(conn.acquire().await.map_err(|err| {
+                    CasbinError::from(AdapterError(Box::new(Error::SqlxError(err))))
+                })?),
+                name: format!("casbin_rule_{}", self.key),
+            };
+
+        // GET_LOCK waits whole seconds
+        let timeout = self.timeout.as_secs() + u64::from(self.timeout.subsec_nanos() > 0);
+        let (locked,): (Option<i64>,) = sqlx::query_as("SELECT GET_LOCK(?, ?)")
+            .bind(&held.name)
+            .bind(timeout)
+            .fetch_one(&mut **held.conn.as_mut().unwrap())
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+        if locked != Some(1) {
+            return Err(self.timed_out());
+        }
+
+        let transaction = conn
+            .begin()
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+        Ok((transaction, held))
+    }
+
+    /// Begins a transaction holding the lock.
+    ///
+    /// sqlite locks the whole database for writing, so the key plays no part.
+    /// Each attempt waits out the busy timeout of the connection, which the
+    /// timeout is rounded up to.
+    #[cfg(feature = "sqlite")]
+    pub(crate) async fn begin(&self, conn: &ConnectionPool) -> Result<(Transaction, Held)> {
+        let started = std::time::Instant::now();
+        loop {
+            match conn.begin_with("BEGIN IMMEDIATE").await {
+                Ok(transaction) => return Ok((transaction, Held {})),
+                Err(sqlx::Error::Database(err)) if is_busy(err.code().as_deref()) => {
+                    if started.elapsed() >= self.timeout {
+                        return Err(self.timed_out());
+                    }
+                    runtime::sleep(BUSY_RETRY).await;
+                }
+                Err(err) => {
+                    return Err(CasbinError::from(AdapterError(Box::new(Error::SqlxError(
+                        err,
+                    )))));
+                }
+            }
+        }
+    }
+}
+
+/// Whether an extended result code is one of `SQLITE_BUSY`.
+#[cfg(feature = "sqlite")]
+fn is_busy(code: Option<&str>) -> bool {
+    code.and_then(|x| x.parse::<i32>().ok())
+        .is_some_and(|x| x & 0xff == 5)
+}
+
+impl Held {
+    /// Lets go of the lock once the transaction it was taken for has ended.
+    #[cfg(feature = "mysql")]
+    pub(crate) async fn release(mut self) {
+        if let Some(conn) = self.conn.as_mut() {
+            let released = sqlx::query("SELECT RELEASE_LOCK(?)")
+                .bind(&self.name)
+                .execute(&mut **conn)
+                .await;
+            if released.is_ok() {
+                self.conn = None;
+            }
+        }
+    }
+
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    pub(crate) async fn release(self) {}
+}
+
+#[cfg(feature = "mysql")]
+impl Drop for Held {
+    fn drop(&mut self) {
+        // closing the session is the only other way to let go of the lock
+        if let Some(conn) = self.conn.as_mut() {
+            conn.close_on_drop();
+        }
+    }
+}
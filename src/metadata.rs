@@ -0,0 +1,227 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::{FromRow, QueryBuilder};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+use crate::{
+    actions::{
+        add_column, column_exists, from_timestamp, push_rule_conditions, timestamp, Connection,
+        ConnectionPool, Database, Visibility, TEXT,
+    },
+    error::*,
+    models::NewCasbinRule,
+};
+
+/// A rule together with the metadata the adapter keeps about it, see
+/// [`SqlxAdapter::with_metadata`](crate::SqlxAdapter::with_metadata).
+///
+/// Rules added before metadata was enabled, or by adapters without it, have
+/// none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMetadata {
+    pub ptype: String,
+    pub rule: Vec<String>,
+    pub created_at: Option<SystemTime>,
+    pub updated_at: Option<SystemTime>,
+    pub created_by: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct MetadataRow {
+    ptype: String,
+    v0: String,
+    v1: String,
+    v2: String,
+    v3: String,
+    v4: String,
+    v5: String,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    created_by: Option<String>,
+    comment: Option<String>,
+}
+
+impl From<MetadataRow> for RuleMetadata {
+    fn from(row: MetadataRow) -> Self {
+        let mut rule = vec![row.v0, row.v1, row.v2, row.v3, row.v4, row.v5];
+        while rule.last().is_some_and(|x| x.is_empty()) {
+            rule.pop();
+        }
+
+        RuleMetadata {
+            ptype: row.ptype,
+            rule,
+            created_at: row.created_at.map(from_timestamp),
+            updated_at: row.updated_at.map(from_timestamp),
+            created_by: row.created_by,
+            comment: row.comment,
+        }
+    }
+}
+
+pub(crate) async fn add_columns(conn: &ConnectionPool) -> Result<()> {
+    add_column(conn, "created_at", "BIGINT").await?;
+    add_column(conn, "updated_at", "BIGINT").await?;
+    add_column(conn, "created_by", TEXT).await?;
+    add_column(conn, "comment", TEXT).await
+}
+
+pub(crate) async fn columns_exist(conn: &ConnectionPool) -> bool {
+    column_exists(conn, "created_at").await
+        && column_exists(conn, "updated_at").await
+        && column_exists(conn, "created_by").await
+        && column_exists(conn, "comment").await
+}
+
+/// Sets the metadata of freshly inserted `rules`.
+pub(crate) async fn stamp(
+    conn: &mut Connection,
+    rules: &[NewCasbinRule<'_>],
+    created_by: &str,
+    comment: Option<&str>,
+) -> Result<()> {
+    let now = timestamp(SystemTime::now());
+
+    for rule in rules {
+        let values = [rule.v0, rule.v1, rule.v2, rule.v3, rule.v4, rule.v5];
+        let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET created_at = ");
+        query
+            .push_bind(now)
+            .push(", updated_at = ")
+            .push_bind(now)
+            .push(", created_by = ")
+            .push_bind(created_by.to_owned())
+            .push(", comment = ")
+            .push_bind(comment.map(|x| x.to_owned()));
+        push_rule_conditions(
+            &mut query,
+            rule.ptype,
+            values.iter().map(|x| x.to_string()).collect(),
+        );
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn set_comment(
+    conn: &ConnectionPool,
+    pt: &str,
+    rule: Vec<String>,
+    comment: Option<&str>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET comment = ");
+    query
+        .push_bind(comment.map(|x| x.to_owned()))
+        .push(", updated_at = ")
+        .push_bind(timestamp(SystemTime::now()));
+    push_rule_conditions(&mut query, pt, rule);
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Sets the `updated_at` of the row with id `id` to now.
+pub(crate) async fn touch(conn: &mut Connection, id: Uuid) -> Result<()> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET updated_at = ");
+    query
+        .push_bind(timestamp(SystemTime::now()))
+        .push(" WHERE uuid = ")
+        .push_bind(id.to_string());
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn list(
+    conn: &ConnectionPool,
+    visibility: Visibility,
+) -> Result<Vec<RuleMetadata>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT ptype, v0, v1, v2, v3, v4, v5, created_at, updated_at, created_by, comment
+            FROM casbin_rule WHERE 1 = 1",
+    );
+    visibility.push_conditions(&mut query);
+    query.push(" ORDER BY id");
+
+    query
+        .build_query_as::<MetadataRow>()
+        .fetch_all(conn)
+        .await
+        .map(|rows| rows.into_iter().map(RuleMetadata::from).collect())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    use crate::SqlxAdapter;
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_metadata() {
+        use casbin::Adapter;
+
+        let mut adapter = test_util::adapter().await.with_metadata().await.unwrap();
+
+        let scoped = to_owned(vec!["meta_alice", "meta_data1", "read"]);
+        let explicit = to_owned(vec!["meta_alice", "meta_data2", "read"]);
+
+        SqlxAdapter::scope_actor("meta_admin", async {
+            assert!(adapter.add_policy("p", "p", scoped.clone()).await.unwrap());
+            assert!(adapter
+                .add_policy_with_metadata("p", explicit.clone(), Some("meta_bot"), Some("TICKET-1"))
+                .await
+                .unwrap());
+        })
+        .await;
+        assert!(adapter
+            .set_comment("p", scoped.clone(), Some("on-call"))
+            .await
+            .unwrap());
+
+        let rules = adapter.list_with_metadata().await.unwrap();
+        let scoped_meta = rules.iter().find(|x| x.rule == scoped).unwrap();
+        assert_eq!(Some("meta_admin".to_owned()), scoped_meta.created_by);
+        assert_eq!(Some("on-call".to_owned()), scoped_meta.comment);
+        assert!(scoped_meta.created_at.is_some());
+        assert!(scoped_meta.updated_at >= scoped_meta.created_at);
+
+        let explicit_meta = rules.iter().find(|x| x.rule == explicit).unwrap();
+        assert_eq!(Some("meta_bot".to_owned()), explicit_meta.created_by);
+        assert_eq!(Some("TICKET-1".to_owned()), explicit_meta.comment);
+
+        // adapters created once the columns are there fill them in too
+        let detected = to_owned(vec!["meta_alice", "meta_data3", "read"]);
+        let mut plain = test_util::adapter().await;
+        assert!(plain.add_policy("p", "p", detected.clone()).await.unwrap());
+        let rules = adapter.list_with_metadata().await.unwrap();
+        let detected_meta = rules.iter().find(|x| x.rule == detected).unwrap();
+        assert!(detected_meta.created_at.is_some());
+
+        assert!(adapter
+            .remove_policies("p", "p", vec![scoped, explicit, detected])
+            .await
+            .unwrap());
+    }
+}
@@ -28,7 +28,7 @@ pub(crate) struct CasbinRule {
     pub v5: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct NewCasbinRule<'a> {
     pub ptype: &'a str,
     pub v0: &'a str,
@@ -0,0 +1,277 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::{FromRow, QueryBuilder};
+use std::time::SystemTime;
+
+use crate::{
+    actions::{from_timestamp, timestamp, Connection, ConnectionPool, Database},
+    change::PolicyChange,
+    error::*,
+};
+
+#[cfg(feature = "postgres")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_outbox (
+                    id BIGSERIAL PRIMARY KEY,
+                    payload TEXT NOT NULL,
+                    created_at BIGINT NOT NULL,
+                    delivered_at BIGINT
+                    );";
+
+#[cfg(feature = "mysql")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_outbox (
+                    id BIGINT NOT NULL AUTO_INCREMENT,
+                    payload LONGTEXT NOT NULL,
+                    created_at BIGINT NOT NULL,
+                    delivered_at BIGINT,
+                    PRIMARY KEY(id)
+                ) ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+// AUTOINCREMENT keeps SQLite from reusing the ids of pruned events.
+#[cfg(feature = "sqlite")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_rule_outbox (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    payload TEXT NOT NULL,
+                    created_at BIGINT NOT NULL,
+                    delivered_at BIGINT
+                    );";
+
+/// A change waiting in the outbox, see
+/// [`SqlxAdapter::with_outbox`](crate::SqlxAdapter::with_outbox).
+///
+/// `id` increases with every event, so sinks can use it to drop events
+/// handed to them twice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub change: PolicyChange,
+    pub created_at: SystemTime,
+}
+
+#[derive(Debug, FromRow)]
+struct OutboxRow {
+    id: i64,
+    payload: String,
+    created_at: i64,
+}
+
+pub(crate) async fn create_table(conn: &ConnectionPool) -> Result<()> {
+    sqlx::query(CREATE_TABLE)
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(())
+}
+
+/// Whether the outbox table is there, in which case every writer has to put
+/// its writes into it.
+pub(crate) async fn exists(conn: &ConnectionPool) -> bool {
+    sqlx::query("SELECT id FROM casbin_rule_outbox WHERE 1 = 0")
+        .execute(conn)
+        .await
+        .is_ok()
+}
+
+/// Puts `changes` into the outbox, one event each.
+pub(crate) async fn record(conn: &mut Connection, changes: &[PolicyChange]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let created_at = timestamp(SystemTime::now());
+    let mut query =
+        QueryBuilder::<Database>::new("INSERT INTO casbin_rule_outbox ( payload, created_at ) ");
+    query.push_values(changes, |mut row, change| {
+        row.push_bind(change.to_json()).push_bind(created_at);
+    });
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(())
+}
+
+/// The oldest `limit` events not delivered yet, oldest first.
+///
+/// Events that do not decode are returned as [`Error::UndecodableEvent`].
+pub(crate) async fn pending(
+    conn: &ConnectionPool,
+    limit: i64,
+) -> Result<Vec<std::result::Result<OutboxEvent, Error>>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, payload, created_at FROM casbin_rule_outbox
+            WHERE delivered_at IS NULL ORDER BY id LIMIT ",
+    );
+    query.push_bind(limit);
+
+    let rows = query
+        .build_query_as::<OutboxRow>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| match PolicyChange::from_json(&row.payload) {
+            Some(change) => Ok(OutboxEvent {
+                id: row.id,
+                change,
+                created_at: from_timestamp(row.created_at),
+            }),
+            None => Err(Error::UndecodableEvent {
+                id: row.id,
+                payload: row.payload,
+            }),
+        })
+        .collect())
+}
+
+pub(crate) async fn mark_delivered(conn: &ConnectionPool, id: i64) -> Result<()> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule_outbox SET delivered_at = ");
+    query
+        .push_bind(timestamp(SystemTime::now()))
+        .push(" WHERE id = ")
+        .push_bind(id);
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(())
+}
+
+/// Deletes the events delivered before `older_than`.
+pub(crate) async fn prune(conn: &ConnectionPool, older_than: SystemTime) -> Result<u64> {
+    let mut query =
+        QueryBuilder::<Database>::new("DELETE FROM casbin_rule_outbox WHERE delivered_at < ");
+    query.push_bind(timestamp(older_than));
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, to_owned};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_outbox() {
+        use casbin::Adapter;
+        use futures::future;
+        use std::{
+            io,
+            time::{Duration, SystemTime},
+        };
+
+        let mut adapter = test_util::adapter().await.with_outbox().await.unwrap();
+        let pool = ConnectionPool::connect(test_util::URL).await.unwrap();
+        let undecodable = "outbox_undecodable";
+
+        // clear out whatever an earlier run left behind
+        sqlx::query("DELETE FROM casbin_rule_outbox WHERE payload = 'outbox_undecodable'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        adapter
+            .relay_outbox(i64::MAX, |_| future::ready(Ok::<_, io::Error>(())))
+            .await
+            .unwrap();
+
+        let rule = to_owned(vec!["outbox_alice", "outbox_data1", "read"]);
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+
+        // a failing sink leaves the event pending
+        assert!(adapter
+            .relay_outbox(10, |_| future::ready(Err(io::Error::other("sink is down"))))
+            .await
+            .is_err());
+
+        // other tests put their writes into the outbox at the same time
+        let ours = |change: &PolicyChange| match change {
+            PolicyChange::Added { rules, .. } | PolicyChange::Removed { rules, .. } => {
+                rules.contains(&rule)
+            }
+            _ => false,
+        };
+
+        // adapters created once the table is there put their writes into it
+        let mut plain = test_util::adapter().await;
+        let mut seen = vec![];
+        assert!(plain.remove_policy("p", "p", rule.clone()).await.unwrap());
+        let delivered = adapter
+            .relay_outbox(i64::MAX, |event| {
+                seen.push(event);
+                future::ready(Ok::<_, io::Error>(()))
+            })
+            .await
+            .unwrap();
+        assert!(delivered >= 2);
+        seen.retain(|x| ours(&x.change));
+        assert!(seen[0].id < seen[1].id);
+        assert_eq!(
+            vec![
+                PolicyChange::Added {
+                    ptype: "p".to_owned(),
+                    rules: vec![rule.clone()],
+                },
+                PolicyChange::Removed {
+                    ptype: "p".to_owned(),
+                    rules: vec![rule.clone()],
+                },
+            ],
+            seen.into_iter().map(|x| x.change).collect::<Vec<_>>()
+        );
+
+        let mut again = vec![];
+        adapter
+            .relay_outbox(i64::MAX, |event| {
+                again.push(event);
+                future::ready(Ok::<_, io::Error>(()))
+            })
+            .await
+            .unwrap();
+        assert!(!again.iter().any(|x| ours(&x.change)));
+
+        // an event that does not decode stops the relay and stays pending
+        let mut query = QueryBuilder::<Database>::new(
+            "INSERT INTO casbin_rule_outbox ( payload, created_at ) VALUES ( ",
+        );
+        query.push_bind(undecodable).push(", 0 )");
+        query.build().execute(&pool).await.unwrap();
+        for _ in 0..2 {
+            match adapter
+                .relay_outbox(i64::MAX, |_| future::ready(Ok::<_, io::Error>(())))
+                .await
+            {
+                Err(CasbinError::AdapterError(AdapterError(err))) => assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::UndecodableEvent { payload, .. }) if payload == undecodable
+                )),
+                _ => panic!("an undecodable event must stop the relay"),
+            }
+        }
+        sqlx::query("DELETE FROM casbin_rule_outbox WHERE payload = 'outbox_undecodable'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            adapter
+                .prune_outbox(SystemTime::now() + Duration::from_secs(1))
+                .await
+                .unwrap()
+                >= 2
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use futures::{stream, Stream};
+use sqlx::QueryBuilder;
+use std::collections::VecDeque;
+
+use crate::{
+    actions::{push_field_conditions, push_rule_conditions, ConnectionPool, Database, Visibility},
+    error::*,
+    models::CasbinRule,
+};
+
+/// How many rules [`stream`] reads at a time.
+const STREAM_PAGE: i64 = 1000;
+
+/// A stored rule, as returned by
+/// [`SqlxAdapter::list_rules`](crate::SqlxAdapter::list_rules).
+///
+/// `id` is the row id, which orders the rules and serves as the cursor for
+/// the next page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredRule {
+    pub id: i64,
+    pub ptype: String,
+    pub rule: Vec<String>,
+}
+
+impl From<CasbinRule> for StoredRule {
+    // row ids are already i64 on sqlite
+    #[cfg_attr(feature = "sqlite", allow(clippy::useless_conversion))]
+    fn from(row: CasbinRule) -> Self {
+        let mut rule = vec![row.v0, row.v1, row.v2, row.v3, row.v4, row.v5];
+        while rule.last().is_some_and(|x| x.is_empty()) {
+            rule.pop();
+        }
+
+        StoredRule {
+            id: i64::from(row.id),
+            ptype: row.ptype,
+            rule,
+        }
+    }
+}
+
+/// Selects the rules the query methods of
+/// [`SqlxAdapter`](crate::SqlxAdapter) look at.
+///
+/// Like [`Adapter::remove_filtered_policy`](casbin::Adapter::remove_filtered_policy),
+/// `field_values` match the fields from `field_index` on, and an empty value
+/// matches anything. The default filter selects every rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleFilter {
+    pub ptype: Option<String>,
+    pub field_index: usize,
+    pub field_values: Vec<String>,
+}
+
+impl RuleFilter {
+    /// Selects the rules of `ptype`.
+    pub fn new<S: Into<String>>(ptype: S) -> Self {
+        RuleFilter {
+            ptype: Some(ptype.into()),
+            ..RuleFilter::default()
+        }
+    }
+
+    /// Also requires the fields from `field_index` on to match `field_values`.
+    pub fn with_fields(mut self, field_index: usize, field_values: Vec<String>) -> Self {
+        self.field_index = field_index;
+        self.field_values = field_values;
+        self
+    }
+
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Database>) {
+        if self.field_index + self.field_values.len() > 6 {
+            // there are only six fields, so nothing can match
+            query.push(" AND 1 = 0");
+            return;
+        }
+
+        if let Some(ptype) = &self.ptype {
+            query.push(" AND ptype = ").push_bind(ptype.clone());
+        }
+        push_field_conditions(query, self.field_index, &self.field_values);
+    }
+}
+
+pub(crate) async fn list(
+    conn: &ConnectionPool,
+    filter: &RuleFilter,
+    after_id: i64,
+    limit: i64,
+    visibility: Visibility,
+) -> Result<Vec<StoredRule>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE id > ",
+    );
+    query.push_bind(after_id);
+    filter.push_conditions(&mut query);
+    visibility.push_conditions(&mut query);
+    query.push(" ORDER BY id LIMIT ").push_bind(limit);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map(|rows| rows.into_iter().map(StoredRule::from).collect())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Streams the rules matching `filter`, reading them a page at a time so
+/// that only one page is held in memory.
+pub(crate) fn stream(
+    conn: &ConnectionPool,
+    filter: RuleFilter,
+    visibility: Visibility,
+) -> impl Stream<Item = Result<StoredRule>> + '_ {
+    let state = (0, VecDeque::new(), false);
+    stream::try_unfold(state, move |(after_id, mut page, mut done)| {
+        let filter = filter.clone();
+        async move {
+            if page.is_empty() && !done {
+                page = list(conn, &filter, after_id, STREAM_PAGE, visibility)
+                    .await?
+                    .into();
+                done = page.len() < STREAM_PAGE as usize;
+            }
+
+            Ok(page.pop_front().map(|rule: StoredRule| {
+                let after_id = rule.id;
+                (rule, (after_id, page, done))
+            }))
+        }
+    })
+}
+
+pub(crate) async fn count(
+    conn: &ConnectionPool,
+    filter: &RuleFilter,
+    visibility: Visibility,
+) -> Result<u64> {
+    let mut query = QueryBuilder::<Database>::new("SELECT COUNT(*) FROM casbin_rule WHERE 1 = 1");
+    filter.push_conditions(&mut query);
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<(i64,)>()
+        .fetch_one(conn)
+        .await
+        .map(|(count,)| count as u64)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn exists(
+    conn: &ConnectionPool,
+    pt: &str,
+    rule: Vec<String>,
+    visibility: Visibility,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("SELECT COUNT(*) FROM casbin_rule");
+    push_rule_conditions(&mut query, pt, rule);
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<(i64,)>()
+        .fetch_one(conn)
+        .await
+        .map(|(count,)| count > 0)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn distinct_values(
+    conn: &ConnectionPool,
+    filter: &RuleFilter,
+    field: usize,
+    visibility: Visibility,
+) -> Result<Vec<String>> {
+    if field > 5 {
+        return Ok(vec![]);
+    }
+
+    let mut query = QueryBuilder::<Database>::new(format!(
+        "SELECT DISTINCT v{0} FROM casbin_rule WHERE v{0} <> ''",
+        field
+    ));
+    filter.push_conditions(&mut query);
+    visibility.push_conditions(&mut query);
+    query.push(format!(" ORDER BY v{}", field));
+
+    query
+        .build_query_as::<(String,)>()
+        .fetch_all(conn)
+        .await
+        .map(|rows| rows.into_iter().map(|(value,)| value).collect())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    use crate::RuleFilter;
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_query() {
+        use casbin::Adapter;
+        use futures::TryStreamExt;
+
+        let mut adapter = test_util::adapter().await;
+
+        let rules = vec![
+            to_owned(vec!["query_alice", "query_data1", "read"]),
+            to_owned(vec!["query_alice", "query_data2", "read"]),
+            to_owned(vec!["query_bob", "query_data1", "write"]),
+        ];
+        assert!(adapter.add_policies("p", "p", rules.clone()).await.unwrap());
+
+        let filter = RuleFilter::new("p").with_fields(1, to_owned(vec!["query_data1"]));
+        assert_eq!(2, adapter.count_rules(&filter).await.unwrap());
+        assert_eq!(
+            to_owned(vec!["query_alice", "query_bob"]),
+            adapter.distinct_values(&filter, 0).await.unwrap()
+        );
+
+        // page through the rules of query_alice one at a time
+        let filter = RuleFilter::new("p").with_fields(0, to_owned(vec!["query_alice"]));
+        let first = adapter.list_rules(&filter, 0, 1).await.unwrap();
+        assert_eq!(1, first.len());
+        assert_eq!(rules[0], first[0].rule);
+        let second = adapter.list_rules(&filter, first[0].id, 1).await.unwrap();
+        assert_eq!(1, second.len());
+        assert_eq!(rules[1], second[0].rule);
+        assert!(adapter
+            .list_rules(&filter, second[0].id, 1)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let streamed = adapter
+            .stream_rules(&filter)
+            .map_ok(|x| x.rule)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(rules[..2].to_vec(), streamed);
+
+        assert!(adapter.rule_exists("p", rules[2].clone()).await.unwrap());
+        assert!(adapter
+            .remove_policies("p", "p", rules.clone())
+            .await
+            .unwrap());
+        assert!(!adapter.rule_exists("p", rules[2].clone()).await.unwrap());
+        assert_eq!(0, adapter.count_rules(&filter).await.unwrap());
+    }
+}
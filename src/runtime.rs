@@ -0,0 +1,90 @@
+use std::{future::Future, time::Duration};
+
+#[cfg(any(
+    feature = "runtime-tokio",
+    feature = "runtime-tokio-native-tls",
+    feature = "runtime-tokio-rustls"
+))]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(future);
+}
+
+#[cfg(all(
+    any(
+        feature = "runtime-async-std-native-tls",
+        feature = "runtime-async-std-rustls"
+    ),
+    not(any(
+        feature = "runtime-tokio",
+        feature = "runtime-tokio-native-tls",
+        feature = "runtime-tokio-rustls"
+    ))
+))]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    async_std::task::spawn(future);
+}
+
+#[cfg(any(
+    feature = "runtime-tokio",
+    feature = "runtime-tokio-native-tls",
+    feature = "runtime-tokio-rustls"
+))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(all(
+    any(
+        feature = "runtime-async-std-native-tls",
+        feature = "runtime-async-std-rustls"
+    ),
+    not(any(
+        feature = "runtime-tokio",
+        feature = "runtime-tokio-native-tls",
+        feature = "runtime-tokio-rustls"
+    ))
+))]
+pub(crate) async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await;
+}
+
+/// Runs `f` on a thread where blocking is fine and waits for its result.
+#[cfg(any(
+    feature = "runtime-tokio",
+    feature = "runtime-tokio-native-tls",
+    feature = "runtime-tokio-rustls"
+))]
+pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
+#[cfg(all(
+    any(
+        feature = "runtime-async-std-native-tls",
+        feature = "runtime-async-std-rustls"
+    ),
+    not(any(
+        feature = "runtime-tokio",
+        feature = "runtime-tokio-native-tls",
+        feature = "runtime-tokio-rustls"
+    ))
+))]
+pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    async_std::task::spawn_blocking(f).await
+}
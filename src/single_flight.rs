@@ -0,0 +1,140 @@
+use casbin::{Filter, Result};
+use futures::{
+    channel::oneshot,
+    future::{FutureExt, Shared},
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use crate::snapshot::Rules;
+
+/// What a load reads: the whole policy or the rules matching a filter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum LoadKey {
+    Policy,
+    Filtered { p: Vec<String>, g: Vec<String> },
+}
+
+impl LoadKey {
+    pub(crate) fn filtered(f: &Filter<'_>) -> Self {
+        LoadKey::Filtered {
+            p: f.p.iter().map(|x| x.to_string()).collect(),
+            g: f.g.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+type Flight = Shared<oneshot::Receiver<Arc<Rules>>>;
+
+/// The loads in flight, which loads of the same rules starting meanwhile
+/// wait for instead of reading the rules again, see
+/// [`SqlxAdapter::with_single_flight`](crate::SqlxAdapter::with_single_flight).
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    flights: Mutex<HashMap<LoadKey, Flight>>,
+}
+
+impl SingleFlight {
+    /// Runs `load` unless a load of `key` is in flight already, in which case
+    /// its result is shared.
+    ///
+    /// Should the shared load fail, `load` runs after all, so each caller
+    /// gets an error of its own.
+    pub(crate) async fn load<F, Fut>(&self, key: LoadKey, load: F) -> Result<Arc<Rules>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Rules>>,
+    {
+        let (sender, flight) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(flight) => (None, Some(flight.clone())),
+                None => {
+                    let (sender, receiver) = oneshot::channel();
+                    flights.insert(key.clone(), receiver.shared());
+                    (Some(sender), None)
+                }
+            }
+        };
+
+        if let Some(flight) = flight {
+            if let Ok(rules) = flight.await {
+                return Ok(rules);
+            }
+            return load().await.map(Arc::new);
+        }
+
+        // lands the flight even if this load is dropped halfway
+        let landing = Landing {
+            flights: &self.flights,
+            key,
+        };
+        let rules = Arc::new(load().await?);
+        drop(landing);
+
+        if let Some(sender) = sender {
+            // fails only when nobody waited
+            let _ = sender.send(rules.clone());
+        }
+        Ok(rules)
+    }
+}
+
+struct Landing<'f> {
+    flights: &'f Mutex<HashMap<LoadKey, Flight>>,
+    key: LoadKey,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        self.flights.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_single_flight() {
+        let flights = SingleFlight::default();
+        let loads = AtomicUsize::new(0);
+        let load = || async {
+            loads.fetch_add(1, Ordering::SeqCst);
+            runtime::sleep(Duration::from_millis(100)).await;
+            let mut rules = Rules::new();
+            rules.insert(("p".to_owned(), vec!["flight_alice".to_owned()]));
+            Ok(rules)
+        };
+
+        let (first, second) = futures::future::join(
+            flights.load(LoadKey::Policy, load),
+            flights.load(LoadKey::Policy, load),
+        )
+        .await;
+        assert_eq!(1, loads.load(Ordering::SeqCst));
+        assert_eq!(first.unwrap(), second.unwrap());
+
+        // a later load reads the rules again
+        flights.load(LoadKey::Policy, load).await.unwrap();
+        assert_eq!(2, loads.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,196 @@
+use casbin::Model;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    actions::{from_timestamp, timestamp},
+    runtime,
+};
+
+/// The rules of a policy as `(ptype, rule)` pairs.
+pub(crate) type Rules = BTreeSet<(String, Vec<String>)>;
+
+/// Called when the policy loaded from a snapshot turns out to be out of date.
+pub(crate) type OutdatedCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Where the snapshot of the policy is kept and for how long it may stand in
+/// for the database, see
+/// [`SqlxAdapter::with_snapshot`](crate::SqlxAdapter::with_snapshot).
+pub(crate) struct Snapshots {
+    path: PathBuf,
+    max_staleness: Duration,
+    /// Only the first load may come from the snapshot.
+    started: AtomicBool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    written_at: i64,
+    /// The policy version the rules were read or saved at, kept for
+    /// [`SqlxAdapter::with_optimistic_save`](crate::SqlxAdapter::with_optimistic_save).
+    #[serde(default)]
+    version: Option<i64>,
+    rules: Rules,
+}
+
+impl Snapshots {
+    pub(crate) fn new(path: PathBuf, max_staleness: Duration) -> Self {
+        Snapshots {
+            path,
+            max_staleness,
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// The rules of the snapshot, and the policy version they are at, if this
+    /// is the first load and the snapshot is recent enough.
+    pub(crate) fn startup_rules(&self) -> Option<(Rules, Option<i64>)> {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let snapshot: Snapshot = serde_json::from_slice(&fs::read(&self.path).ok()?).ok()?;
+        let age = SystemTime::now()
+            .duration_since(from_timestamp(snapshot.written_at))
+            .unwrap_or_default();
+        (age <= self.max_staleness).then_some((snapshot.rules, snapshot.version))
+    }
+
+    /// Replaces the snapshot with `rules`, which are at `version`.
+    ///
+    /// Writes to a temporary file of its own first, so neither a crash nor a
+    /// concurrent write leaves a truncated snapshot behind, on a blocking
+    /// thread, as the policy may be large.
+    pub(crate) async fn write(&self, rules: Rules, version: Option<i64>) -> io::Result<()> {
+        let snapshot = Snapshot {
+            written_at: timestamp(SystemTime::now()),
+            version,
+            rules,
+        };
+        let path = self.path.clone();
+
+        runtime::spawn_blocking(move || {
+            let mut temporary = path.clone().into_os_string();
+            temporary.push(format!(".{}.tmp", Uuid::new_v4()));
+
+            let written = serde_json::to_vec(&snapshot)
+                .map_err(io::Error::from)
+                .and_then(|bytes| fs::write(&temporary, bytes))
+                .and_then(|_| fs::rename(&temporary, &path));
+            if written.is_err() {
+                let _ = fs::remove_file(&temporary);
+            }
+            written
+        })
+        .await
+    }
+}
+
+/// The `p` and `g` rules held by `m`.
+pub(crate) fn rules_of(m: &dyn Model) -> Rules {
+    let mut rules = Rules::new();
+    for sec in ["p", "g"] {
+        if let Some(ast_map) = m.get_model().get(sec) {
+            for (ptype, ast) in ast_map {
+                rules.extend(
+                    ast.get_policy()
+                        .iter()
+                        .map(|rule| (ptype.clone(), rule.clone())),
+                );
+            }
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    #[cfg(feature = "tokio")]
+    use crate::PolicyChange;
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_snapshot() {
+        use casbin::{Adapter, DefaultModel, Model};
+        use std::{
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                Arc,
+            },
+            time::Duration,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("casbin_snapshot_{}.json", uuid::Uuid::new_v4()));
+        let new_adapter = || async {
+            test_util::adapter()
+                .await
+                .with_snapshot(path.clone(), Duration::from_secs(60))
+        };
+        let new_model = || DefaultModel::from_file("examples/rbac_model.conf");
+
+        let before = to_owned(vec!["snapshot_alice", "snapshot_data1", "read"]);
+        let after = to_owned(vec!["snapshot_alice", "snapshot_data2", "read"]);
+
+        // without a snapshot the first load reads the database and writes one
+        let mut adapter = new_adapter().await;
+        assert!(adapter.add_policy("p", "p", before.clone()).await.unwrap());
+        let mut m = new_model().await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(path.exists());
+        assert!(adapter.add_policy("p", "p", after.clone()).await.unwrap());
+
+        // a fresh start loads the snapshot, then learns it is out of date
+        let outdated = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&outdated);
+        let mut restarted = new_adapter()
+            .await
+            .on_snapshot_outdated(move || flag.store(true, Ordering::SeqCst));
+        #[cfg(feature = "tokio")]
+        let mut changes = restarted.subscribe();
+        let mut m = new_model().await.unwrap();
+        restarted.load_policy(&mut m).await.unwrap();
+        assert!(m.has_policy("p", "p", before.clone()));
+        assert!(!m.has_policy("p", "p", after.clone()));
+        #[cfg(feature = "tokio")]
+        assert_eq!(PolicyChange::Saved, changes.recv().await.unwrap());
+        for _ in 0..50 {
+            if outdated.load(Ordering::SeqCst) {
+                break;
+            }
+            crate::runtime::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(outdated.load(Ordering::SeqCst));
+
+        m.clear_policy();
+        restarted.load_policy(&mut m).await.unwrap();
+        assert!(m.has_policy("p", "p", after.clone()));
+
+        assert!(adapter
+            .remove_policies("p", "p", vec![before, after])
+            .await
+            .unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
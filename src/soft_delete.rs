@@ -0,0 +1,303 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::{error::Error as SqlxError, FromRow, QueryBuilder};
+use std::time::SystemTime;
+
+use crate::{
+    actions::{
+        add_column, from_timestamp, push_field_conditions, push_rule_conditions, timestamp,
+        Connection, ConnectionPool, Database,
+    },
+    error::*,
+    models::{CasbinRule, NewCasbinRule},
+};
+
+/// A soft deleted rule, see
+/// [`SqlxAdapter::with_soft_delete`](crate::SqlxAdapter::with_soft_delete).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeletedRule {
+    pub ptype: String,
+    pub rule: Vec<String>,
+    pub deleted_at: SystemTime,
+}
+
+#[derive(Debug, FromRow)]
+struct DeletedRow {
+    ptype: String,
+    v0: String,
+    v1: String,
+    v2: String,
+    v3: String,
+    v4: String,
+    v5: String,
+    deleted_at: i64,
+}
+
+impl From<DeletedRow> for DeletedRule {
+    fn from(row: DeletedRow) -> Self {
+        let mut rule = vec![row.v0, row.v1, row.v2, row.v3, row.v4, row.v5];
+        while rule.last().is_some_and(|x| x.is_empty()) {
+            rule.pop();
+        }
+
+        DeletedRule {
+            ptype: row.ptype,
+            rule,
+            deleted_at: from_timestamp(row.deleted_at),
+        }
+    }
+}
+
+pub(crate) async fn add_columns(conn: &ConnectionPool) -> Result<()> {
+    add_column(conn, "deleted_at", "BIGINT").await
+}
+
+pub(crate) async fn remove_policy(
+    conn: &mut Connection,
+    pt: &str,
+    rule: Vec<String>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET deleted_at = ");
+    query.push_bind(timestamp(SystemTime::now()));
+    push_rule_conditions(&mut query, pt, rule);
+    query.push(" AND deleted_at IS NULL");
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn remove_policies(
+    conn: &mut Connection,
+    pt: &str,
+    rules: Vec<Vec<String>>,
+) -> Result<bool> {
+    for rule in rules {
+        if !remove_policy(conn, pt, rule).await? {
+            return Err(CasbinError::from(AdapterError(Box::new(Error::SqlxError(
+                SqlxError::RowNotFound,
+            )))));
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) async fn remove_filtered_policy(
+    conn: &mut Connection,
+    pt: &str,
+    field_index: usize,
+    field_values: Vec<String>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET deleted_at = ");
+    query
+        .push_bind(timestamp(SystemTime::now()))
+        .push(" WHERE ptype = ")
+        .push_bind(pt.to_owned());
+    push_field_conditions(&mut query, field_index, &field_values);
+    query.push(" AND deleted_at IS NULL");
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() >= 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Soft deletes the rows of `rules`, returning how many were deleted.
+pub(crate) async fn delete_rules(conn: &mut Connection, rules: &[CasbinRule]) -> Result<u64> {
+    let mut deleted = 0;
+
+    for chunk in rules.chunks(1000) {
+        let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET deleted_at = ");
+        query
+            .push_bind(timestamp(SystemTime::now()))
+            .push(" WHERE deleted_at IS NULL AND id IN (");
+        let mut ids = query.separated(", ");
+        for rule in chunk {
+            ids.push_bind(rule.id);
+        }
+        ids.push_unseparated(")");
+        deleted += query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map(|n| n.rows_affected())
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(deleted)
+}
+
+/// Deletes the soft deleted copies of `rules` for good, which would otherwise
+/// keep them from being added again.
+pub(crate) async fn purge_rules(conn: &mut Connection, rules: &[NewCasbinRule<'_>]) -> Result<()> {
+    for rule in rules {
+        let values = [rule.v0, rule.v1, rule.v2, rule.v3, rule.v4, rule.v5];
+        let mut query = QueryBuilder::<Database>::new("DELETE FROM casbin_rule");
+        push_rule_conditions(
+            &mut query,
+            rule.ptype,
+            values.iter().map(|x| x.to_string()).collect(),
+        );
+        query.push(" AND deleted_at IS NOT NULL");
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn list_deleted(conn: &ConnectionPool) -> Result<Vec<DeletedRule>> {
+    sqlx::query_as::<_, DeletedRow>(
+        "SELECT ptype, v0, v1, v2, v3, v4, v5, deleted_at FROM casbin_rule
+            WHERE deleted_at IS NOT NULL ORDER BY deleted_at",
+    )
+    .fetch_all(conn)
+    .await
+    .map(|rows| rows.into_iter().map(DeletedRule::from).collect())
+    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn restore(conn: &mut Connection, pt: &str, rule: Vec<String>) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET deleted_at = NULL");
+    push_rule_conditions(&mut query, pt, rule);
+    query.push(" AND deleted_at IS NOT NULL");
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+pub(crate) async fn purge_deleted(conn: &ConnectionPool, older_than: SystemTime) -> Result<u64> {
+    let mut query = QueryBuilder::<Database>::new("DELETE FROM casbin_rule WHERE deleted_at < ");
+    query.push_bind(timestamp(older_than));
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_soft_delete() {
+        use casbin::{Adapter, DefaultModel, Model};
+        use std::time::{Duration, SystemTime};
+
+        let mut adapter = test_util::adapter().await.with_soft_delete().await.unwrap();
+
+        let rule = to_owned(vec!["soft_alice", "soft_data", "read"]);
+        let is_deleted = |deleted: &[crate::DeletedRule]| {
+            deleted.iter().any(|x| x.ptype == "p" && x.rule == rule)
+        };
+
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(adapter.remove_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(!adapter.remove_policy("p", "p", rule.clone()).await.unwrap());
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(!m.has_policy("p", "p", rule.clone()));
+        assert!(is_deleted(&adapter.list_deleted().await.unwrap()));
+
+        // adapters created once the column is there leave them out too
+        m.clear_policy();
+        test_util::adapter()
+            .await
+            .load_policy(&mut m)
+            .await
+            .unwrap();
+        assert!(!m.has_policy("p", "p", rule.clone()));
+
+        assert!(adapter.restore("p", rule.clone()).await.unwrap());
+        assert!(!adapter.restore("p", rule.clone()).await.unwrap());
+        m.clear_policy();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.has_policy("p", "p", rule.clone()));
+
+        // a soft deleted rule can be added again
+        assert!(adapter
+            .remove_filtered_policy("p", "p", 0, to_owned(vec!["soft_alice"]))
+            .await
+            .unwrap());
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(!is_deleted(&adapter.list_deleted().await.unwrap()));
+
+        assert!(adapter.remove_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(
+            adapter
+                .purge_deleted(SystemTime::now() + Duration::from_secs(1))
+                .await
+                .unwrap()
+                >= 1
+        );
+        assert!(!is_deleted(&adapter.list_deleted().await.unwrap()));
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_save_soft_deleted() {
+        use casbin::{Adapter, DefaultModel, Model};
+
+        // metadata makes save_policy only insert the rules that are missing
+        let mut adapter = test_util::adapter()
+            .await
+            .with_soft_delete()
+            .await
+            .unwrap()
+            .with_metadata()
+            .await
+            .unwrap();
+
+        let rule = to_owned(vec!["soft_save_alice", "soft_save_data", "read"]);
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(adapter.remove_policy("p", "p", rule.clone()).await.unwrap());
+
+        // reload right before saving, so rules of other tests are kept
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        m.add_policy("p", "p", rule.clone());
+        adapter.save_policy(&mut m).await.unwrap();
+        assert!(adapter.rule_exists("p", rule.clone()).await.unwrap());
+        assert!(!adapter
+            .list_deleted()
+            .await
+            .unwrap()
+            .iter()
+            .any(|x| x.ptype == "p" && x.rule == rule));
+
+        assert!(adapter.remove_policy("p", "p", rule).await.unwrap());
+    }
+}
@@ -0,0 +1,137 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::QueryBuilder;
+
+use crate::{
+    actions::{
+        add_column, push_rule_conditions, Connection, ConnectionPool, Database, Visibility, TEXT,
+    },
+    error::*,
+    models::{CasbinRule, NewCasbinRule},
+};
+
+pub(crate) async fn add_columns(conn: &ConnectionPool) -> Result<()> {
+    add_column(conn, "tag", TEXT).await
+}
+
+/// Tags freshly inserted `rules` with `tag`.
+pub(crate) async fn set(
+    conn: &mut Connection,
+    rules: &[NewCasbinRule<'_>],
+    tag: &str,
+) -> Result<()> {
+    for rule in rules {
+        let values = [rule.v0, rule.v1, rule.v2, rule.v3, rule.v4, rule.v5];
+        let mut query = QueryBuilder::<Database>::new("UPDATE casbin_rule SET tag = ");
+        query.push_bind(tag.to_owned());
+        push_rule_conditions(
+            &mut query,
+            rule.ptype,
+            values.iter().map(|x| x.to_string()).collect(),
+        );
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    }
+
+    Ok(())
+}
+
+/// Reads the rules of the policy tagged `tag`.
+pub(crate) async fn select<'e, E>(
+    conn: E,
+    tag: &str,
+    visibility: Visibility,
+) -> Result<Vec<CasbinRule>>
+where
+    E: sqlx::Executor<'e, Database = Database>,
+{
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE tag = ",
+    );
+    query.push_bind(tag.to_owned());
+    visibility.push_conditions(&mut query);
+    query.push(" ORDER BY id");
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_tags() {
+        use casbin::{Adapter, DefaultModel, Filter, Model};
+
+        let mut adapter = test_util::adapter().await.with_tags().await.unwrap();
+
+        let provisioned = vec![
+            to_owned(vec!["tag_alice", "tag_data1", "read"]),
+            to_owned(vec!["tag_alice", "tag_data2", "read"]),
+        ];
+        let manual = to_owned(vec!["tag_alice", "tag_data3", "read"]);
+
+        assert!(adapter
+            .add_policies_with_tag("p", provisioned.clone(), "tag_terraform")
+            .await
+            .unwrap());
+        assert!(adapter.add_policy("p", "p", manual.clone()).await.unwrap());
+
+        // adapters created once the column is there tag rules too
+        let detected = to_owned(vec!["tag_alice", "tag_data4", "read"]);
+        let mut plain = test_util::adapter().await;
+        assert!(plain
+            .add_policy_with_tag("p", detected.clone(), "tag_detected")
+            .await
+            .unwrap());
+        assert_eq!(1, plain.remove_by_tag("tag_detected").await.unwrap());
+
+        let listed = adapter.list_by_tag("tag_terraform").await.unwrap();
+        assert_eq!(
+            provisioned
+                .iter()
+                .map(|x| ("p".to_owned(), x.clone()))
+                .collect::<Vec<_>>(),
+            listed
+        );
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        let filter = Filter {
+            p: vec!["tag_alice"],
+            g: vec![],
+        };
+        adapter
+            .load_filtered_policy_by_tag(&mut m, filter, "tag_terraform")
+            .await
+            .unwrap();
+        assert!(m.has_policy("p", "p", provisioned[0].clone()));
+        assert!(m.has_policy("p", "p", provisioned[1].clone()));
+        assert!(!m.has_policy("p", "p", manual.clone()));
+
+        assert_eq!(2, adapter.remove_by_tag("tag_terraform").await.unwrap());
+        assert!(adapter
+            .list_by_tag("tag_terraform")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(adapter.remove_policy("p", "p", manual).await.unwrap());
+    }
+}
@@ -0,0 +1,235 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use sqlx::QueryBuilder;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::SystemTime,
+};
+
+use crate::{
+    actions::{add_column, timestamp, Connection, ConnectionPool, Database, Visibility},
+    error::*,
+    models::{CasbinRule, NewCasbinRule},
+};
+
+/// Keeps the task started by
+/// [`SqlxAdapter::spawn_reaper`](crate::SqlxAdapter::spawn_reaper) running;
+/// dropping it stops the task.
+pub struct Reaper {
+    _alive: Arc<()>,
+}
+
+impl Reaper {
+    pub(crate) fn new() -> (Self, Weak<()>) {
+        let alive = Arc::new(());
+        let weak = Arc::downgrade(&alive);
+        (Reaper { _alive: alive }, weak)
+    }
+}
+
+/// Up to when the rules that became valid have been announced, shared by an
+/// adapter and its clones.
+pub(crate) struct Activations(Mutex<SystemTime>);
+
+impl Activations {
+    pub(crate) fn new() -> Self {
+        Activations(Mutex::new(SystemTime::now()))
+    }
+
+    pub(crate) fn since(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn announced(&self, until: SystemTime) {
+        let mut since = self.0.lock().unwrap();
+        *since = (*since).max(until);
+    }
+}
+
+pub(crate) async fn add_columns(conn: &ConnectionPool) -> Result<()> {
+    add_column(conn, "valid_from", "BIGINT").await?;
+    add_column(conn, "valid_until", "BIGINT").await
+}
+
+/// Whether a rule with this window applies right now.
+pub(crate) fn is_valid(valid_from: Option<SystemTime>, valid_until: Option<SystemTime>) -> bool {
+    let now = SystemTime::now();
+    valid_from.is_none_or(|from| from <= now) && valid_until.is_none_or(|until| until > now)
+}
+
+pub(crate) async fn add_policy(
+    conn: &mut Connection,
+    rule: NewCasbinRule<'_>,
+    valid_from: Option<SystemTime>,
+    valid_until: Option<SystemTime>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::<Database>::new(
+        "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5, valid_from, valid_until ) ",
+    );
+    query.push_values(std::iter::once(rule), |mut row, rule| {
+        row.push_bind(rule.ptype.to_owned())
+            .push_bind(rule.v0.to_owned())
+            .push_bind(rule.v1.to_owned())
+            .push_bind(rule.v2.to_owned())
+            .push_bind(rule.v3.to_owned())
+            .push_bind(rule.v4.to_owned())
+            .push_bind(rule.v5.to_owned())
+            .push_bind(valid_from.map(timestamp))
+            .push_bind(valid_until.map(timestamp));
+    });
+    query
+        .build()
+        .execute(conn)
+        .await
+        .map(|n| n.rows_affected() == 1)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Reads the rules whose `valid_until` has passed, leaving out soft deleted
+/// ones.
+pub(crate) async fn expired(
+    conn: &ConnectionPool,
+    visibility: Visibility,
+) -> Result<Vec<CasbinRule>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE valid_until <= ",
+    );
+    query.push_bind(timestamp(SystemTime::now()));
+    Visibility {
+        validity: false,
+        ..visibility
+    }
+    .push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+/// Reads the rules whose `valid_from` passed after `since` and no later than
+/// `until`, leaving out those that do not apply right now.
+pub(crate) async fn activated(
+    conn: &ConnectionPool,
+    visibility: Visibility,
+    since: SystemTime,
+    until: SystemTime,
+) -> Result<Vec<CasbinRule>> {
+    let mut query = QueryBuilder::<Database>::new(
+        "SELECT id, ptype, v0, v1, v2, v3, v4, v5 FROM casbin_rule WHERE valid_from > ",
+    );
+    query
+        .push_bind(timestamp(since))
+        .push(" AND valid_from <= ")
+        .push_bind(timestamp(until));
+    visibility.push_conditions(&mut query);
+
+    query
+        .build_query_as::<CasbinRule>()
+        .fetch_all(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    #[cfg(feature = "tokio")]
+    use crate::PolicyChange;
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_validity() {
+        use casbin::{Adapter, DefaultModel, Model};
+        use std::time::{Duration, SystemTime};
+
+        let mut adapter = test_util::adapter()
+            .await
+            .with_policy_version()
+            .await
+            .unwrap()
+            .with_validity()
+            .await
+            .unwrap();
+
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        let active = to_owned(vec!["valid_alice", "valid_data1", "read"]);
+        let pending = to_owned(vec!["valid_alice", "valid_data2", "read"]);
+        let expired = to_owned(vec!["valid_alice", "valid_data3", "read"]);
+
+        assert!(adapter
+            .add_policy_with_validity("p", active.clone(), None, Some(now + hour))
+            .await
+            .unwrap());
+        assert!(adapter
+            .add_policy_with_validity("p", pending.clone(), Some(now + hour), None)
+            .await
+            .unwrap());
+        assert!(adapter
+            .add_policy_with_validity("p", expired.clone(), Some(now - hour), Some(now))
+            .await
+            .unwrap());
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.has_policy("p", "p", active.clone()));
+        assert!(!m.has_policy("p", "p", pending.clone()));
+        assert!(!m.has_policy("p", "p", expired.clone()));
+
+        // adapters created once the columns are there apply them too
+        m.clear_policy();
+        test_util::adapter()
+            .await
+            .load_policy(&mut m)
+            .await
+            .unwrap();
+        assert!(!m.has_policy("p", "p", pending.clone()));
+
+        #[cfg(feature = "tokio")]
+        let mut changes = adapter.subscribe();
+        assert!(adapter.reap_expired().await.unwrap() >= 1);
+        #[cfg(feature = "tokio")]
+        assert!(matches!(
+            changes.recv().await.unwrap(),
+            PolicyChange::Removed { ptype, rules } if ptype == "p" && rules.contains(&expired)
+        ));
+        assert!(!adapter.remove_policy("p", "p", expired).await.unwrap());
+
+        // a rule becoming valid is announced by the next reap
+        let soon = to_owned(vec!["valid_alice", "valid_data4", "read"]);
+        let valid_from = SystemTime::now() + Duration::from_millis(200);
+        assert!(adapter
+            .add_policy_with_validity("p", soon.clone(), Some(valid_from), None)
+            .await
+            .unwrap());
+        let before = adapter.current_version().await.unwrap();
+        crate::runtime::sleep(Duration::from_millis(300)).await;
+        adapter.reap_expired().await.unwrap();
+        assert!(adapter.current_version().await.unwrap() > before);
+        #[cfg(feature = "tokio")]
+        loop {
+            if let PolicyChange::Added { ptype, rules } = changes.recv().await.unwrap() {
+                if ptype == "p" && rules.contains(&soon) {
+                    break;
+                }
+            }
+        }
+
+        assert!(adapter
+            .remove_policies("p", "p", vec![active, pending, soon])
+            .await
+            .unwrap());
+    }
+}
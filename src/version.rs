@@ -0,0 +1,348 @@
+use casbin::{error::AdapterError, Error as CasbinError, Result};
+use std::sync::Mutex;
+
+use crate::{
+    actions::{Connection, ConnectionPool},
+    error::*,
+};
+
+#[cfg(feature = "postgres")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_policy_version (
+                    id INTEGER PRIMARY KEY,
+                    version BIGINT NOT NULL
+                    );";
+
+#[cfg(feature = "mysql")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_policy_version (
+                    id INT NOT NULL,
+                    version BIGINT NOT NULL,
+                    PRIMARY KEY(id)
+                ) ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+#[cfg(feature = "sqlite")]
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS casbin_policy_version (
+                    id INTEGER PRIMARY KEY,
+                    version BIGINT NOT NULL
+                    );";
+
+#[cfg(feature = "postgres")]
+const INSERT_ROW: &str =
+    "INSERT INTO casbin_policy_version ( id, version ) VALUES ( 1, 0 ) ON CONFLICT DO NOTHING";
+
+#[cfg(feature = "mysql")]
+const INSERT_ROW: &str = "INSERT IGNORE INTO casbin_policy_version ( id, version ) VALUES ( 1, 0 )";
+
+#[cfg(feature = "sqlite")]
+const INSERT_ROW: &str =
+    "INSERT OR IGNORE INTO casbin_policy_version ( id, version ) VALUES ( 1, 0 )";
+
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+const LOCK_VERSION: &str = "SELECT version FROM casbin_policy_version WHERE id = 1 FOR UPDATE";
+
+// sqlite locks the whole database for the first write of a transaction
+#[cfg(feature = "sqlite")]
+const LOCK_VERSION: &str = "SELECT version FROM casbin_policy_version WHERE id = 1";
+
+/// The policy version as of the last load by an adapter or its clones, see
+/// [`SqlxAdapter::with_optimistic_save`](crate::SqlxAdapter::with_optimistic_save).
+#[derive(Default)]
+pub(crate) struct Observed(Mutex<Option<i64>>);
+
+impl Observed {
+    pub(crate) fn get(&self) -> Option<i64> {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn set(&self, version: i64) {
+        *self.0.lock().unwrap() = Some(version);
+    }
+
+    /// Moves on to `version` after a write of our own, unless another write
+    /// came in between, which the next load has to pick up first.
+    pub(crate) fn advance(&self, version: i64) {
+        let mut observed = self.0.lock().unwrap();
+        if *observed == Some(version - 1) {
+            *observed = Some(version);
+        }
+    }
+}
+
+pub(crate) async fn create_table(conn: &ConnectionPool) -> Result<()> {
+    sqlx::query(CREATE_TABLE)
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    sqlx::query(INSERT_ROW)
+        .execute(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(())
+}
+
+/// Whether the version table is there, in which case every writer has to
+/// keep the version.
+pub(crate) async fn exists(conn: &ConnectionPool) -> bool {
+    sqlx::query("SELECT version FROM casbin_policy_version WHERE 1 = 0")
+        .execute(conn)
+        .await
+        .is_ok()
+}
+
+/// Increments the version, returning the new one.
+///
+/// The row stays locked until the surrounding transaction ends, which orders
+/// concurrent writes.
+pub(crate) async fn bump(conn: &mut Connection) -> Result<i64> {
+    sqlx::query("UPDATE casbin_policy_version SET version = version + 1 WHERE id = 1")
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    current(conn).await
+}
+
+/// Reads the version, keeping concurrent writes from changing it until the
+/// surrounding transaction ends.
+pub(crate) async fn lock(conn: &mut Connection) -> Result<i64> {
+    let (version,): (i64,) = sqlx::query_as(LOCK_VERSION)
+        .fetch_one(conn)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(version)
+}
+
+pub(crate) async fn current<'e, E>(conn: E) -> Result<i64>
+where
+    E: sqlx::Executor<'e, Database = crate::actions::Database>,
+{
+    let (version,): (i64,) =
+        sqlx::query_as("SELECT version FROM casbin_policy_version WHERE id = 1")
+            .fetch_one(conn)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::SqlxError(err)))))?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_policy_version() {
+        use casbin::{Adapter, DefaultModel};
+
+        let mut adapter = test_util::adapter()
+            .await
+            .with_policy_version()
+            .await
+            .unwrap();
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+
+        let before = adapter.current_version().await.unwrap();
+        let rule = to_owned(vec!["version_alice", "version_data", "read"]);
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        let after = adapter.current_version().await.unwrap();
+        assert!(after > before);
+
+        let loaded = adapter
+            .load_policy_if_changed(&mut m, before)
+            .await
+            .unwrap();
+        assert!(loaded.is_some_and(|version| version >= after));
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            None,
+            adapter
+                .load_policy_if_changed(&mut m, loaded)
+                .await
+                .unwrap()
+        );
+
+        assert!(adapter.remove_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(adapter.current_version().await.unwrap() > after);
+
+        // an adapter started once the table is there keeps the version too
+        let mut plain = test_util::adapter().await;
+        let before = adapter.current_version().await.unwrap();
+        assert!(plain.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(adapter.current_version().await.unwrap() > before);
+        assert!(plain.remove_policy("p", "p", rule).await.unwrap());
+    }
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_optimistic_save() {
+        use crate::Error;
+        use casbin::{error::AdapterError, Adapter, DefaultModel, Error as CasbinError, Model};
+        use std::time::Duration;
+
+        let mut admin = test_util::adapter()
+            .await
+            .with_optimistic_save()
+            .await
+            .unwrap();
+        let mut other = test_util::adapter()
+            .await
+            .with_policy_version()
+            .await
+            .unwrap();
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        admin.load_policy(&mut m).await.unwrap();
+
+        // writes of our own keep the loaded version current
+        let own = to_owned(vec!["optimistic_alice", "optimistic_data1", "read"]);
+        assert!(admin.add_policy("p", "p", own.clone()).await.unwrap());
+        m.add_policy("p", "p", own.clone());
+
+        let theirs = to_owned(vec!["optimistic_bob", "optimistic_data2", "read"]);
+        assert!(other.add_policy("p", "p", theirs.clone()).await.unwrap());
+
+        match admin.save_policy(&mut m).await {
+            Err(CasbinError::AdapterError(AdapterError(err))) => {
+                assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::ConcurrentModification { .. })
+                ));
+            }
+            _ => panic!("saving over a concurrent write must fail"),
+        }
+        assert!(admin.rule_exists("p", theirs.clone()).await.unwrap());
+
+        assert!(admin
+            .remove_policies("p", "p", vec![own, theirs.clone()])
+            .await
+            .unwrap());
+
+        // a policy that was never loaded cannot be saved over the stored one
+        let mut fresh = test_util::adapter()
+            .await
+            .with_optimistic_save()
+            .await
+            .unwrap();
+        let mut empty = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        match fresh.save_policy(&mut empty).await {
+            Err(CasbinError::AdapterError(AdapterError(err))) => {
+                assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::ConcurrentModification { loaded: None, .. })
+                ));
+            }
+            _ => panic!("saving a policy that was never loaded must fail"),
+        }
+
+        // a policy loaded from a snapshot is at the version of the snapshot
+        let path =
+            std::env::temp_dir().join(format!("casbin_optimistic_{}.json", uuid::Uuid::new_v4()));
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        test_util::adapter()
+            .await
+            .with_optimistic_save()
+            .await
+            .unwrap()
+            .with_snapshot(path.clone(), Duration::from_secs(60))
+            .load_policy(&mut m)
+            .await
+            .unwrap();
+        let mut restarted = test_util::adapter()
+            .await
+            .with_optimistic_save()
+            .await
+            .unwrap()
+            .with_snapshot(path.clone(), Duration::from_secs(60));
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        restarted.load_policy(&mut m).await.unwrap();
+        assert!(other.add_policy("p", "p", theirs.clone()).await.unwrap());
+        match restarted.save_policy(&mut m).await {
+            Err(CasbinError::AdapterError(AdapterError(err))) => {
+                assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::ConcurrentModification {
+                        loaded: Some(_),
+                        ..
+                    })
+                ));
+            }
+            _ => panic!("saving a snapshot over a concurrent write must fail"),
+        }
+        assert!(other.remove_policy("p", "p", theirs).await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+    // saving over the whole table would remove the rules of concurrent tests
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_force_save() {
+        use crate::SqlxAdapter;
+        use casbin::{Adapter, DefaultModel, Model};
+
+        let database = test_util::PrivateDatabase::new().await;
+        let mut admin = SqlxAdapter::new_with_pool(database.pool.clone())
+            .await
+            .unwrap()
+            .with_optimistic_save()
+            .await
+            .unwrap();
+        let mut other = SqlxAdapter::new_with_pool(database.pool.clone())
+            .await
+            .unwrap();
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+            .await
+            .unwrap();
+        admin.load_policy(&mut m).await.unwrap();
+        let own = to_owned(vec!["force_alice", "force_data1", "read"]);
+        m.add_policy("p", "p", own.clone());
+
+        let theirs = to_owned(vec!["force_bob", "force_data2", "read"]);
+        assert!(other.add_policy("p", "p", theirs.clone()).await.unwrap());
+        assert!(admin.save_policy(&mut m).await.is_err());
+
+        admin.force_save_policy(&mut m).await.unwrap();
+        assert!(admin.rule_exists("p", own).await.unwrap());
+        assert!(!admin.rule_exists("p", theirs).await.unwrap());
+
+        // the policy is at the version it saved, so saving again works
+        admin.save_policy(&mut m).await.unwrap();
+
+        database.remove().await;
+    }
+}
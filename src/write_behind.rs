@@ -0,0 +1,324 @@
+use crate::SqlxAdapter;
+use futures::channel::oneshot;
+use std::{collections::BTreeMap, mem, sync::Mutex};
+
+/// A buffered write of a single rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Add,
+    Remove,
+}
+
+/// The buffered writes of a single rule, applied remove first, each with the
+/// actor that buffered it.
+#[derive(Debug, Default, Clone)]
+struct Queued {
+    remove: Option<String>,
+    add: Option<String>,
+}
+
+/// The writes waiting to be flushed, at most one of each kind per rule.
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    ops: BTreeMap<(String, Vec<String>), Queued>,
+}
+
+impl Pending {
+    /// Queues `op` on `rule` by `actor`, returning whether that changed what
+    /// is pending.
+    ///
+    /// Removing a rule whose add is queued cancels the add but still removes
+    /// the rule, and adding a rule whose removal is queued keeps both, since
+    /// the rule may be stored by others before the flush.
+    fn push(&mut self, ptype: String, rule: Vec<String>, op: Op, actor: &str) -> bool {
+        let key = (ptype, rule);
+        let mut queued = self.ops.get(&key).cloned().unwrap_or_default();
+        let changed = match op {
+            Op::Add if queued.add.is_some() => false,
+            Op::Add => {
+                queued.add = Some(actor.to_owned());
+                true
+            }
+            Op::Remove if queued.add.is_some() => {
+                queued.add = None;
+                queued.remove.get_or_insert_with(|| actor.to_owned());
+                true
+            }
+            Op::Remove if queued.remove.is_some() => false,
+            Op::Remove => {
+                queued.remove = Some(actor.to_owned());
+                true
+            }
+        };
+        if queued.remove.is_some() || queued.add.is_some() {
+            self.ops.insert(key, queued);
+        } else {
+            self.ops.remove(&key);
+        }
+        changed
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// The queued rules to `op`, grouped by ptype and actor.
+    pub(crate) fn rules(&self, op: Op) -> BTreeMap<(String, String), Vec<Vec<String>>> {
+        let mut rules: BTreeMap<(String, String), Vec<Vec<String>>> = BTreeMap::new();
+        for ((ptype, rule), queued) in &self.ops {
+            let actor = match op {
+                Op::Add => &queued.add,
+                Op::Remove => &queued.remove,
+            };
+            if let Some(actor) = actor {
+                rules
+                    .entry((ptype.clone(), actor.clone()))
+                    .or_default()
+                    .push(rule.clone());
+            }
+        }
+        rules
+    }
+}
+
+/// The writes buffered by an adapter and its clones, see
+/// [`SqlxAdapter::with_write_behind`](crate::SqlxAdapter::with_write_behind).
+///
+/// The first adapter to buffer a write leaves a copy of itself to flush the
+/// buffer in the background, so that it writes with the configuration the
+/// adapter ended up with. When the last clone is gone, whatever is still
+/// pending is handed to the task flushing the buffer, together with that
+/// copy, which writes it.
+pub(crate) struct WriteBuffer {
+    pending: Mutex<Pending>,
+    max_batch: usize,
+    /// Held while flushing, so that flushes commit in the order they took
+    /// their writes.
+    pub(crate) flushing: futures::lock::Mutex<()>,
+    flusher: Mutex<Option<SqlxAdapter>>,
+    dropped: Mutex<Option<oneshot::Sender<(Pending, SqlxAdapter)>>>,
+}
+
+impl WriteBuffer {
+    pub(crate) fn new(max_batch: usize) -> (Self, oneshot::Receiver<(Pending, SqlxAdapter)>) {
+        let (sender, receiver) = oneshot::channel();
+        let buffer = WriteBuffer {
+            pending: Mutex::new(Pending::default()),
+            max_batch,
+            flushing: futures::lock::Mutex::new(()),
+            flusher: Mutex::new(None),
+            dropped: Mutex::new(Some(sender)),
+        };
+        (buffer, receiver)
+    }
+
+    /// Queues `op` by `actor` on each of `rules`, returning whether that
+    /// changed what is pending and whether the buffer is full.
+    ///
+    /// `flusher` is called for the adapter flushing in the background, unless
+    /// there already is one.
+    pub(crate) fn push<F>(
+        &self,
+        ptype: &str,
+        rules: Vec<Vec<String>>,
+        op: Op,
+        actor: &str,
+        flusher: F,
+    ) -> (bool, bool)
+    where
+        F: FnOnce() -> SqlxAdapter,
+    {
+        {
+            let mut current = self.flusher.lock().unwrap();
+            if current.is_none() {
+                *current = Some(flusher());
+            }
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let mut changed = false;
+        for rule in rules.into_iter().filter(|x| !x.is_empty()) {
+            changed |= pending.push(ptype.to_owned(), rule, op, actor);
+        }
+        (changed, pending.ops.len() >= self.max_batch)
+    }
+
+    /// The adapter flushing in the background, once a write was buffered.
+    pub(crate) fn flusher(&self) -> Option<SqlxAdapter> {
+        self.flusher.lock().unwrap().clone()
+    }
+
+    pub(crate) fn take(&self) -> Pending {
+        mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Queues the writes of a failed flush again, ahead of the ones queued
+    /// since.
+    pub(crate) fn requeue(&self, failed: Pending) {
+        let mut pending = self.pending.lock().unwrap();
+        let newer = mem::replace(&mut *pending, failed);
+        for ((ptype, rule), queued) in newer.ops {
+            if let Some(actor) = &queued.remove {
+                pending.push(ptype.clone(), rule.clone(), Op::Remove, actor);
+            }
+            if let Some(actor) = &queued.add {
+                pending.push(ptype, rule, Op::Add, actor);
+            }
+        }
+    }
+}
+
+impl Drop for WriteBuffer {
+    fn drop(&mut self) {
+        let pending = self.take();
+        if pending.is_empty() {
+            return;
+        }
+
+        let flusher = self.flusher.lock().unwrap().take();
+        if let (Some(dropped), Some(flusher)) = (self.dropped.lock().unwrap().take(), flusher) {
+            // fails only when the flushing task is gone
+            let _ = dropped.send((pending, flusher));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, to_owned};
+    use crate::SqlxAdapter;
+
+    #[cfg_attr(
+        any(
+            feature = "runtime-async-std-native-tls",
+            feature = "runtime-async-std-rustls"
+        ),
+        async_std::test
+    )]
+    #[cfg_attr(
+        any(feature = "runtime-tokio-native-tls", feature = "runtime-tokio-rustls"),
+        tokio::test(flavor = "multi_thread")
+    )]
+    async fn test_write_behind() {
+        use casbin::Adapter;
+        use std::time::Duration;
+
+        let plain = test_util::adapter().await;
+        let mut adapter = plain
+            .clone()
+            .with_write_behind(3, Duration::from_secs(3600));
+
+        let kept = to_owned(vec!["behind_alice", "behind_data1", "read"]);
+        let cancelled = to_owned(vec!["behind_alice", "behind_data2", "read"]);
+        assert!(adapter.add_policy("p", "p", kept.clone()).await.unwrap());
+        assert!(adapter
+            .add_policy("p", "p", cancelled.clone())
+            .await
+            .unwrap());
+        assert!(adapter
+            .remove_policy("p", "p", cancelled.clone())
+            .await
+            .unwrap());
+        assert!(!plain.rule_exists("p", kept.clone()).await.unwrap());
+
+        adapter.flush().await.unwrap();
+        assert!(plain.rule_exists("p", kept.clone()).await.unwrap());
+        assert!(!plain.rule_exists("p", cancelled.clone()).await.unwrap());
+
+        // the removal still applies to a rule stored by others meanwhile
+        let mut direct = plain.clone();
+        assert!(adapter
+            .add_policy("p", "p", cancelled.clone())
+            .await
+            .unwrap());
+        assert!(direct
+            .add_policy("p", "p", cancelled.clone())
+            .await
+            .unwrap());
+        assert!(adapter
+            .remove_policy("p", "p", cancelled.clone())
+            .await
+            .unwrap());
+        adapter.flush().await.unwrap();
+        assert!(!plain.rule_exists("p", cancelled).await.unwrap());
+
+        // adding a rule stored in the meantime does not fail the flush
+        let stored = to_owned(vec!["behind_alice", "behind_data3", "read"]);
+        assert!(adapter.add_policy("p", "p", stored.clone()).await.unwrap());
+        assert!(direct.add_policy("p", "p", stored.clone()).await.unwrap());
+        adapter.flush().await.unwrap();
+        assert!(plain.rule_exists("p", stored.clone()).await.unwrap());
+        assert!(direct.remove_policy("p", "p", stored).await.unwrap());
+
+        // removing and then adding a rule again writes both
+        assert!(adapter.remove_policy("p", "p", kept.clone()).await.unwrap());
+        assert!(!adapter.remove_policy("p", "p", kept.clone()).await.unwrap());
+        assert!(adapter.add_policy("p", "p", kept.clone()).await.unwrap());
+        assert!(!adapter.add_policy("p", "p", kept.clone()).await.unwrap());
+        adapter.flush().await.unwrap();
+        assert!(plain.rule_exists("p", kept.clone()).await.unwrap());
+
+        // a full buffer flushes by itself
+        let batch = (0..3)
+            .map(|i| to_owned(vec!["behind_bob", &format!("behind_data{}", i), "read"]))
+            .collect::<Vec<_>>();
+        assert!(adapter.add_policies("p", "p", batch.clone()).await.unwrap());
+        for rule in &batch {
+            assert!(plain.rule_exists("p", rule.clone()).await.unwrap());
+        }
+
+        // what is still buffered gets written once the adapter is dropped
+        assert!(adapter.remove_policy("p", "p", kept.clone()).await.unwrap());
+        drop(adapter);
+        let mut written = false;
+        for _ in 0..50 {
+            if !plain.rule_exists("p", kept.clone()).await.unwrap() {
+                written = true;
+                break;
+            }
+            crate::runtime::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(written);
+
+        let mut plain = plain;
+        assert!(plain.remove_policies("p", "p", batch).await.unwrap());
+
+        // the background flush writes with the configuration set after
+        // with_write_behind and records the actor that buffered each write
+        let mut adapter = plain
+            .clone()
+            .with_write_behind(8, Duration::from_secs(3600))
+            .with_history()
+            .await
+            .unwrap();
+        let after_id = adapter
+            .history(0, i64::MAX)
+            .await
+            .unwrap()
+            .last()
+            .map_or(0, |x| x.id);
+        let late = to_owned(vec!["behind_carol", "behind_data1", "read"]);
+        SqlxAdapter::scope_actor("behind_admin", async {
+            assert!(adapter.add_policy("p", "p", late.clone()).await.unwrap());
+        })
+        .await;
+        drop(adapter);
+        let mut entries = vec![];
+        for _ in 0..50 {
+            entries = plain
+                .history(after_id, i64::MAX)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|x| x.rule == late)
+                .collect::<Vec<_>>();
+            if !entries.is_empty() {
+                break;
+            }
+            crate::runtime::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(1, entries.len());
+        assert_eq!("behind_admin", entries[0].actor);
+        assert!(plain.remove_policy("p", "p", late).await.unwrap());
+    }
+}